@@ -0,0 +1,34 @@
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use wsocket::apply_mask;
+
+const MASK: [u8; 4] = [0x37, 0xfa, 0x21, 0x3d];
+
+/// The byte at a time loop used before `apply_mask` existed.
+fn apply_mask_bytewise(buf: &mut [u8], mask: [u8; 4]) {
+  buf
+    .iter_mut()
+    .enumerate()
+    .for_each(|(idx, byte)| *byte ^= unsafe { mask.get_unchecked(idx & 3) })
+}
+
+fn mask(c: &mut Criterion) {
+  let mut group = c.benchmark_group("mask");
+
+  for len in [64, 1024, 16 * 1024, 1024 * 1024] {
+    let mut buf = vec![0x42u8; len];
+    group.throughput(Throughput::Bytes(len as u64));
+
+    group.bench_with_input(BenchmarkId::new("bytewise", len), &len, |b, _| {
+      b.iter(|| apply_mask_bytewise(black_box(&mut buf), black_box(MASK)))
+    });
+
+    group.bench_with_input(BenchmarkId::new("apply_mask", len), &len, |b, _| {
+      b.iter(|| apply_mask(black_box(&mut buf), black_box(MASK)))
+    });
+  }
+
+  group.finish();
+}
+
+criterion_group!(benches, mask);
+criterion_main!(benches);
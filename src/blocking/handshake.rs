@@ -0,0 +1,147 @@
+use std::io::{self, Read, Write};
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+
+use crate::accept::sec_websocket_accept;
+use crate::blocking::WebSocket;
+use crate::{WSocketError, WSocketResult, WebSocketConfig};
+
+/// Upper bound for the status line and headers of the handshake response.
+const MAX_RESPONSE_LEN: usize = 8 * 1024;
+
+/// Response of the server to the handshake request.
+#[derive(Debug, Clone)]
+pub struct Response {
+  status: u16,
+  headers: Vec<(String, String)>,
+}
+
+impl Response {
+  pub fn status(&self) -> u16 {
+    self.status
+  }
+
+  /// First value of the header, matching its name case-insensitively.
+  pub fn header(&self, name: &str) -> Option<&str> {
+    self
+      .headers
+      .iter()
+      .find(|(key, _)| key.eq_ignore_ascii_case(name))
+      .map(|(_, value)| value.as_str())
+  }
+
+  pub fn headers(&self) -> &[(String, String)] {
+    &self.headers
+  }
+}
+
+/// Performs the client handshake over an already connected stream, without an http client.
+/// <https://datatracker.ietf.org/doc/html/rfc6455#section-4.1>
+///
+/// The timeouts of the config are applied to streams implementing
+/// [`Timeouts`](crate::blocking::Timeouts) using [`WebSocket::with_timeouts`].
+pub fn handshake<S: Read + Write>(
+  mut stream: S,
+  path: &str,
+  host: &str,
+  port: u16,
+  user_agent: &str,
+  config: WebSocketConfig,
+) -> WSocketResult<(WebSocket<S>, Response)> {
+  let key = STANDARD.encode(rand::random::<[u8; 16]>());
+
+  let request = format!(
+    "GET {path} HTTP/1.1\r\n\
+     Host: {host}:{port}\r\n\
+     Upgrade: websocket\r\n\
+     Connection: upgrade\r\n\
+     Sec-WebSocket-Key: {key}\r\n\
+     Sec-WebSocket-Version: 13\r\n\
+     User-Agent: {user_agent}\r\n\
+     \r\n"
+  );
+  stream.write_all(request.as_bytes())?;
+  stream.flush()?;
+
+  let mut ws = WebSocket::client(stream, config);
+
+  // bytes following the response already belong to the websocket connection
+  let (response, rest) = read_response(&mut ws.stream)?;
+  ws.protocol.receive(&rest);
+
+  verify(&response, &key)?;
+
+  Ok((ws, response))
+}
+
+fn read_response(stream: &mut impl Read) -> WSocketResult<(Response, Vec<u8>)> {
+  let mut buf = Vec::new();
+  let mut chunk = [0; 1024];
+
+  let end = loop {
+    if let Some(pos) = buf.windows(4).position(|window| window == b"\r\n\r\n") {
+      break pos;
+    }
+
+    if buf.len() > MAX_RESPONSE_LEN {
+      return Err(WSocketError::InvalidHttpResponse);
+    }
+
+    match stream.read(&mut chunk) {
+      Ok(0) => return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into()),
+      Ok(len) => buf.extend_from_slice(&chunk[..len]),
+      Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+      Err(err) => return Err(err.into()),
+    }
+  };
+
+  let head = std::str::from_utf8(&buf[..end]).map_err(|_| WSocketError::InvalidHttpResponse)?;
+  let mut lines = head.split("\r\n");
+
+  let status = lines
+    .next()
+    .and_then(|line| line.strip_prefix("HTTP/1.1 "))
+    .and_then(|line| line.split(' ').next())
+    .and_then(|status| status.parse().ok())
+    .ok_or(WSocketError::InvalidHttpResponse)?;
+
+  let headers = lines
+    .map(|line| {
+      line
+        .split_once(':')
+        .map(|(key, value)| (key.trim().to_string(), value.trim().to_string()))
+        .ok_or(WSocketError::InvalidHttpResponse)
+    })
+    .collect::<WSocketResult<_>>()?;
+
+  Ok((Response { status, headers }, buf[end + 4..].to_vec()))
+}
+
+fn verify(response: &Response, key: &str) -> WSocketResult<()> {
+  if response.status != 101 {
+    return Err(WSocketError::InvalidResponseStatus(response.status));
+  }
+
+  if !response
+    .header("upgrade")
+    .map(|h| h.eq_ignore_ascii_case("websocket"))
+    .unwrap_or(false)
+  {
+    return Err(WSocketError::InvalidUpgradeHeader);
+  }
+
+  if !response
+    .header("connection")
+    .map(|h| h.eq_ignore_ascii_case("upgrade"))
+    .unwrap_or(false)
+  {
+    return Err(WSocketError::InvalidConnectionHeader);
+  }
+
+  if response.header("sec-websocket-accept") != Some(&sec_websocket_accept(key.as_bytes())) {
+    return Err(WSocketError::InvalidSecWebSocketAccept);
+  }
+
+  Ok(())
+}
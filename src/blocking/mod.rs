@@ -0,0 +1,356 @@
+//! Blocking WebSocket over [`std::io::Read`] and [`std::io::Write`], for synchronous code without
+//! an async runtime.
+
+use std::io::{self, Cursor, Read, Write};
+use std::net::TcpStream;
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use std::time::Duration;
+
+use tracing::{error, info};
+
+use crate::protocol::Received;
+use crate::{
+  Close, Limits, Masking, Message, OwnedMessage, Protocol, Role, WSocketError, WSocketResult,
+  WebSocketConfig,
+};
+
+#[cfg(feature = "client")]
+pub use handshake::{handshake, Response};
+
+#[cfg(feature = "client")]
+mod handshake;
+#[cfg(test)]
+mod test;
+
+/// Blocking counterpart of [`WebSocket`](crate::WebSocket), built on the same [`Protocol`].
+///
+/// If the stream fails with [`io::ErrorKind::WouldBlock`] or [`io::ErrorKind::TimedOut`], e.g.
+/// after a [read timeout](WebSocket::set_read_timeout) expired, the connection stays open and the
+/// operation can be retried. Partially received frames are kept.
+///
+/// Pings are answered right away while receiving, unless disabled using
+/// [`auto_pong`](WebSocketConfig::auto_pong), and a close frame of the peer is echoed.
+pub struct WebSocket<S> {
+  stream: S,
+  protocol: Protocol,
+  auto_pong: bool,
+  idle_timeout: Option<Duration>,
+  write_timeout: Option<Duration>,
+  closed: bool,
+}
+
+/// Streams, that can limit how long reads and writes block, like [`TcpStream`].
+///
+/// The [`idle_timeout`](WebSocketConfig::idle_timeout) of the config is applied as read timeout
+/// and the [`write_timeout`](WebSocketConfig::write_timeout) as write timeout using
+/// [`WebSocket::with_timeouts`]. Streams without timeouts implement this trait using the default
+/// methods, which ignore the timeouts.
+pub trait Timeouts {
+  fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+    let _ = timeout;
+    Ok(())
+  }
+
+  fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+    let _ = timeout;
+    Ok(())
+  }
+}
+
+impl Timeouts for TcpStream {
+  fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+    TcpStream::set_read_timeout(self, timeout)
+  }
+
+  fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+    TcpStream::set_write_timeout(self, timeout)
+  }
+}
+
+#[cfg(unix)]
+impl Timeouts for UnixStream {
+  fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+    UnixStream::set_read_timeout(self, timeout)
+  }
+
+  fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+    UnixStream::set_write_timeout(self, timeout)
+  }
+}
+
+/// In memory streams never block.
+impl<T> Timeouts for Cursor<T> {}
+
+impl<T: Timeouts + ?Sized> Timeouts for &mut T {
+  fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+    (**self).set_read_timeout(timeout)
+  }
+
+  fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+    (**self).set_write_timeout(timeout)
+  }
+}
+
+impl<T: Timeouts + ?Sized> Timeouts for Box<T> {
+  fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+    (**self).set_read_timeout(timeout)
+  }
+
+  fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+    (**self).set_write_timeout(timeout)
+  }
+}
+
+impl<S> WebSocket<S> {
+  #[inline]
+  pub fn server(stream: S, config: WebSocketConfig) -> Self {
+    Self::from_config(
+      stream,
+      WebSocketConfig {
+        role: Role::Server,
+        ..config
+      },
+    )
+  }
+
+  #[inline]
+  #[cfg(feature = "client")]
+  pub fn client(stream: S, config: WebSocketConfig) -> Self {
+    Self::from_config(
+      stream,
+      WebSocketConfig {
+        role: Role::Client,
+        ..config
+      },
+    )
+  }
+
+  /// Creates a connection acting as the [`role`](WebSocketConfig::role) of the config. Its
+  /// timeouts are only applied to the stream using [`with_timeouts`](Self::with_timeouts).
+  pub fn from_config(stream: S, config: WebSocketConfig) -> Self {
+    let protocol = Protocol::new(config.role, config.read_limits)
+      .with_masking(config.masking)
+      .with_write_limits(config.write_limits)
+      .with_read_buffer_len(config.read_buffer_len);
+
+    Self {
+      stream,
+      protocol,
+      auto_pong: config.auto_pong,
+      idle_timeout: config.idle_timeout,
+      write_timeout: config.write_timeout,
+      closed: false,
+    }
+  }
+}
+
+impl<S: Timeouts> WebSocket<S> {
+  /// Applies the [`idle_timeout`](WebSocketConfig::idle_timeout) of the config as read timeout
+  /// and its [`write_timeout`](WebSocketConfig::write_timeout) as write timeout to the stream.
+  pub fn with_timeouts(self) -> WSocketResult<Self> {
+    self.set_read_timeout(self.idle_timeout)?;
+    self.set_write_timeout(self.write_timeout)?;
+    Ok(self)
+  }
+
+  /// Limits how long [`recv`](WebSocket::recv) blocks waiting for data, [`None`] blocks forever.
+  pub fn set_read_timeout(&self, timeout: Option<Duration>) -> WSocketResult<()> {
+    Ok(self.stream.set_read_timeout(timeout)?)
+  }
+
+  /// Limits how long [`send`](WebSocket::send) blocks on a slow peer, [`None`] blocks forever.
+  pub fn set_write_timeout(&self, timeout: Option<Duration>) -> WSocketResult<()> {
+    Ok(self.stream.set_write_timeout(timeout)?)
+  }
+}
+
+impl<S> WebSocket<S> {
+  #[inline]
+  pub fn with_masking(mut self, masking: Masking) -> Self {
+    self.protocol = self.protocol.with_masking(masking);
+    self
+  }
+
+  /// Limits for received frames and messages, see [`Protocol::with_read_limits`].
+  #[inline]
+  pub fn with_read_limits(mut self, limits: Limits) -> Self {
+    self.protocol = self.protocol.with_read_limits(limits);
+    self
+  }
+
+  /// Limits for sent frames and messages, see [`Protocol::with_write_limits`].
+  #[inline]
+  pub fn with_write_limits(mut self, limits: Limits) -> Self {
+    self.protocol = self.protocol.with_write_limits(limits);
+    self
+  }
+
+  pub fn role(&self) -> Role {
+    self.protocol.role()
+  }
+
+  pub fn is_closed(&self) -> bool {
+    self.closed
+  }
+
+  pub fn get_ref(&self) -> &S {
+    &self.stream
+  }
+
+  pub fn get_mut(&mut self) -> &mut S {
+    &mut self.stream
+  }
+}
+
+impl<S: Read + Write> WebSocket<S> {
+  pub fn recv<'a>(&mut self, buf: &'a mut [u8]) -> WSocketResult<Message<'a>> {
+    if self.closed {
+      return Err(WSocketError::NotConnected);
+    }
+
+    let result = self
+      .read_message()
+      .and_then(|received| self.protocol.message(received)?.copy_into(buf));
+
+    if let Err(ref err) = result {
+      self.on_recv_error(err);
+    }
+
+    let message = result?;
+    self.on_message(&message);
+    Ok(message)
+  }
+
+  /// Receives the next message into a newly allocated buffer, which is sized to fit the payload
+  /// of the message.
+  pub fn recv_owned(&mut self) -> WSocketResult<OwnedMessage> {
+    if self.closed {
+      return Err(WSocketError::NotConnected);
+    }
+
+    let result = self
+      .read_message()
+      .and_then(|received| self.protocol.message(received))
+      .map(OwnedMessage::from);
+
+    if let Err(ref err) = result {
+      self.on_recv_error(err);
+    }
+
+    let message = result?;
+    self.on_message(&message.as_message());
+    Ok(message)
+  }
+
+  /// Sends the message and flushes the stream.
+  pub fn send(&mut self, message: Message<'_>) -> WSocketResult<()> {
+    if self.closed {
+      return Err(WSocketError::NotConnected);
+    }
+
+    let result = self.protocol.send(message).and_then(|_| self.flush());
+
+    if let Err(ref err) = result {
+      self.on_send_error(err);
+    }
+
+    result
+  }
+
+  pub fn close(&mut self, close: Close) -> WSocketResult<()> {
+    self.protocol.close(&close)?;
+    self.closed = true;
+    self.flush()
+  }
+
+  /// Answers pings, if enabled. The ping is returned, even if the pong can't be sent, failing the
+  /// next call instead.
+  fn on_message(&mut self, message: &Message<'_>) {
+    if let Message::Ping(payload) = *message {
+      if self.auto_pong {
+        if let Err(err) = self.send(Message::Pong(payload)) {
+          error!("Failed to answer ping: {}", err);
+        }
+      }
+    }
+  }
+
+  /// Mark stream as closed and echo the close frame of the peer or send one reporting the error,
+  /// if it wasn't an io error.
+  fn on_recv_error(&mut self, err: &WSocketError) {
+    match err {
+      err if is_retryable(err) => {}
+      WSocketError::ConnectionClosed(close) => {
+        info!("marking connection as closed");
+
+        if let Err(err) = self.close(close.reply()) {
+          error!("Failed to echo close frame: {}", err);
+        }
+
+        self.closed = true;
+      }
+      err if !err.is_io_error() => {
+        if let Err(err) = self.close(err.to_close()) {
+          error!("Failed to send close frame: {}", err);
+        }
+
+        self.closed = true;
+      }
+      _ => self.closed = true,
+    }
+  }
+
+  /// Mark stream as closed and send close frame, if error wasn't an io error.
+  fn on_send_error(&mut self, err: &WSocketError) {
+    if is_retryable(err) {
+      return;
+    }
+
+    if !err.is_io_error() {
+      if let Err(err) = self.close(err.to_close()) {
+        error!("Failed to send close frame: {}", err);
+      }
+    }
+
+    self.closed = true;
+  }
+
+  /// Reads until the protocol was able to decode a complete message.
+  fn read_message(&mut self) -> WSocketResult<Received> {
+    loop {
+      if let Some(received) = self.protocol.decode_message()? {
+        return Ok(received);
+      }
+
+      match self.stream.read(self.protocol.input_buf()) {
+        Ok(0) => return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into()),
+        Ok(len) => self.protocol.commit_input(len),
+        Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+        Err(err) => return Err(err.into()),
+      }
+    }
+  }
+
+  /// Writes the output of the protocol, keeping track of partial writes.
+  fn flush(&mut self) -> WSocketResult<()> {
+    while !self.protocol.output().is_empty() {
+      match self.stream.write(self.protocol.output()) {
+        Ok(0) => return Err(io::Error::from(io::ErrorKind::WriteZero).into()),
+        Ok(written) => self.protocol.consume_output(written),
+        Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+        Err(err) => return Err(err.into()),
+      }
+    }
+
+    Ok(self.stream.flush()?)
+  }
+}
+
+/// Whether the operation failed because of a timeout or a non-blocking stream and can be retried.
+fn is_retryable(err: &WSocketError) -> bool {
+  matches!(
+    err,
+    WSocketError::Io(err) if matches!(err.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut)
+  )
+}
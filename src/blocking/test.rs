@@ -0,0 +1,230 @@
+use std::io::{Cursor, Write};
+use std::net::{TcpListener, TcpStream};
+use std::time::Duration;
+
+use crate::blocking::WebSocket;
+use crate::{
+  Close, CloseCode, Limits, Masking, Message, OwnedMessage, WSocketError, WSocketResult,
+  WebSocketConfig,
+};
+
+fn config(max_payload_len: usize) -> WebSocketConfig {
+  WebSocketConfig {
+    read_limits: Limits::new(max_payload_len),
+    write_limits: Limits::new(max_payload_len),
+    ..WebSocketConfig::default()
+  }
+}
+
+#[test]
+fn test_recv_masked_ping() -> WSocketResult<()> {
+  let input = [
+    0x89, 0x85, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d, 0x51, 0x58,
+  ];
+  let mut ws = WebSocket::server(Cursor::new(input.to_vec()), config(5));
+
+  let mut buf = [0u8; 5];
+  assert_eq!(ws.recv(&mut buf)?, Message::Ping(b"Hello"));
+
+  Ok(())
+}
+
+#[test]
+fn test_send_and_close() -> WSocketResult<()> {
+  let mut ws = WebSocket::server(Cursor::new(Vec::new()), config(1024));
+
+  ws.send(Message::Binary(b"Hello"))?;
+  ws.close(Close::new(CloseCode::Normal, None))?;
+  assert!(ws.is_closed());
+
+  assert_eq!(
+    ws.get_ref().get_ref(),
+    &[0x82, 0x05, 0x48, 0x65, 0x6c, 0x6c, 0x6f, 0x88, 0x02, 0x03, 0xe8]
+  );
+  assert!(matches!(
+    ws.send(Message::Binary(b"Hello")),
+    Err(WSocketError::NotConnected)
+  ));
+
+  Ok(())
+}
+
+#[test]
+fn test_recv_close_echoes_it() {
+  let input = [0x88, 0x02, 0x03, 0xe9];
+  let mut ws =
+    WebSocket::server(Cursor::new(input.to_vec()), config(1024)).with_masking(Masking::Lenient);
+
+  assert!(matches!(
+    ws.recv_owned(),
+    Err(WSocketError::ConnectionClosed(close)) if close == Close::new(CloseCode::Away, None)
+  ));
+  assert!(ws.is_closed());
+  assert_eq!(
+    &ws.get_ref().get_ref()[input.len()..],
+    [0x88, 0x02, 0x03, 0xe9]
+  );
+}
+
+#[test]
+fn test_recv_ping_answers_it() -> WSocketResult<()> {
+  let input = [0x89, 0x02, 0x48, 0x69];
+  let mut ws =
+    WebSocket::server(Cursor::new(input.to_vec()), config(1024)).with_masking(Masking::Lenient);
+
+  assert_eq!(ws.recv_owned()?, OwnedMessage::Ping(b"Hi".to_vec()));
+  assert_eq!(
+    &ws.get_ref().get_ref()[input.len()..],
+    [0x8a, 0x02, 0x48, 0x69]
+  );
+
+  let config = WebSocketConfig {
+    auto_pong: false,
+    ..config(1024)
+  };
+  let mut ws =
+    WebSocket::server(Cursor::new(input.to_vec()), config).with_masking(Masking::Lenient);
+
+  assert_eq!(ws.recv_owned()?, OwnedMessage::Ping(b"Hi".to_vec()));
+  assert_eq!(ws.get_ref().get_ref().len(), input.len());
+
+  Ok(())
+}
+
+#[test]
+fn test_recv_error_sends_close() {
+  let input = [0x82, 0x01, 0x48];
+  let mut ws = WebSocket::server(Cursor::new(input.to_vec()), config(1024));
+
+  assert!(matches!(
+    ws.recv_owned(),
+    Err(WSocketError::FrameMustBeMasked)
+  ));
+  assert!(ws.is_closed());
+
+  let output = &ws.get_ref().get_ref()[input.len()..];
+  assert_eq!(output[0], 0x88);
+  assert_eq!(output[2..4], [0x03, 0xea]);
+}
+
+#[test]
+fn test_recv_ping_when_pong_fails() {
+  /// Returns the input, but fails all writes.
+  struct ReadOnly(Cursor<Vec<u8>>);
+
+  impl std::io::Read for ReadOnly {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+      self.0.read(buf)
+    }
+  }
+
+  impl Write for ReadOnly {
+    fn write(&mut self, _: &[u8]) -> std::io::Result<usize> {
+      Err(std::io::ErrorKind::BrokenPipe.into())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+      Ok(())
+    }
+  }
+
+  let input = [0x89, 0x02, 0x48, 0x69];
+  let mut ws = WebSocket::server(ReadOnly(Cursor::new(input.to_vec())), config(1024))
+    .with_masking(Masking::Lenient);
+
+  assert!(matches!(
+    ws.recv_owned(),
+    Ok(OwnedMessage::Ping(payload)) if payload == b"Hi"
+  ));
+  assert!(ws.is_closed());
+}
+
+#[test]
+fn test_read_timeout_keeps_partial_frame() -> WSocketResult<()> {
+  let listener = TcpListener::bind("127.0.0.1:0")?;
+  let mut peer = TcpStream::connect(listener.local_addr()?)?;
+  let (stream, _) = listener.accept()?;
+
+  let config = WebSocketConfig {
+    idle_timeout: Some(Duration::from_millis(10)),
+    ..config(1024)
+  };
+  let mut ws = WebSocket::server(stream, config)
+    .with_masking(Masking::Lenient)
+    .with_timeouts()?;
+
+  peer.write_all(&[0x82, 0x05, 0x48, 0x65])?;
+
+  assert!(matches!(ws.recv_owned(), Err(WSocketError::Io(_))));
+  assert!(!ws.is_closed());
+
+  peer.write_all(&[0x6c, 0x6c, 0x6f])?;
+  assert_eq!(ws.recv_owned()?, OwnedMessage::Binary(b"Hello".to_vec()));
+
+  Ok(())
+}
+
+#[cfg(feature = "client")]
+#[test]
+fn test_handshake() -> WSocketResult<()> {
+  use std::io::Read;
+
+  use crate::accept::sec_websocket_accept;
+  use crate::blocking::handshake;
+
+  /// Answers the handshake request, followed by a binary frame.
+  struct Server {
+    request: Vec<u8>,
+    response: Cursor<Vec<u8>>,
+  }
+
+  impl Read for Server {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+      if self.response.get_ref().is_empty() {
+        let request = String::from_utf8(self.request.clone()).unwrap();
+        let key = request
+          .lines()
+          .find_map(|line| line.strip_prefix("Sec-WebSocket-Key: "))
+          .unwrap();
+
+        let mut response = format!(
+          "HTTP/1.1 101 Switching Protocols\r\n\
+           Upgrade: websocket\r\n\
+           Connection: Upgrade\r\n\
+           Sec-WebSocket-Accept: {}\r\n\
+           \r\n",
+          sec_websocket_accept(key.as_bytes())
+        )
+        .into_bytes();
+        response.extend_from_slice(&[0x82, 0x05, 0x48, 0x65, 0x6c, 0x6c, 0x6f]);
+
+        self.response = Cursor::new(response);
+      }
+
+      self.response.read(buf)
+    }
+  }
+
+  impl Write for Server {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+      self.request.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+      Ok(())
+    }
+  }
+
+  let server = Server {
+    request: Vec::new(),
+    response: Cursor::new(Vec::new()),
+  };
+
+  let (mut ws, response) = handshake(server, "/", "localhost", 80, "wsocket", config(1024))?;
+  assert_eq!(response.status(), 101);
+  assert_eq!(response.header("upgrade"), Some("websocket"));
+
+  assert_eq!(ws.recv_owned()?, OwnedMessage::Binary(b"Hello".to_vec()));
+
+  Ok(())
+}
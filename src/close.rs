@@ -1,6 +1,12 @@
-use std::fmt::{Display, Formatter};
+#[cfg(feature = "alloc")]
+use alloc::string::String;
+#[cfg(feature = "alloc")]
+use alloc::vec::Vec;
+use core::fmt::{Display, Formatter};
 
-use crate::{WSocketError, WSocketResult};
+use crate::WSocketError;
+#[cfg(feature = "alloc")]
+use crate::WSocketResult;
 
 /// When closing an established connection an endpoint MAY indicate a reason for closure.
 /// <https://datatracker.ietf.org/doc/html/rfc6455#section-7.4.1>
@@ -65,6 +71,7 @@ impl TryFrom<u16> for CloseCode {
       1005 => Ok(Self::NoStatusRcvd),
       1006 => Ok(Self::Abnormal),
       1007 => Ok(Self::InvalidPayload),
+      1008 => Ok(Self::PolicyViolation),
       1009 => Ok(Self::MessageTooBig),
       1010 => Ok(Self::MandatoryExt),
       1011 => Ok(Self::InternalError),
@@ -75,7 +82,7 @@ impl TryFrom<u16> for CloseCode {
 }
 
 impl Display for CloseCode {
-  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+  fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
     match self {
       CloseCode::Normal => write!(f, "Normal"),
       CloseCode::Away => write!(f, "Normal"),
@@ -93,30 +100,64 @@ impl Display for CloseCode {
   }
 }
 
+#[cfg(feature = "alloc")]
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub struct Close {
   code: CloseCode,
   reason: Option<String>,
 }
 
+#[cfg(feature = "alloc")]
 impl Close {
   pub fn new(code: CloseCode, reason: Option<String>) -> Self {
     Self { code, reason }
   }
 
+  /// Length of the encoded close frame payload.
+  #[cfg(feature = "tokio")]
+  pub(crate) fn payload_len(&self) -> usize {
+    if self.is_bodyless() {
+      return 0;
+    }
+
+    2 + self.reason.as_ref().map_or(0, String::len)
+  }
+
+  /// Whether this is encoded as a close frame without payload, which the peer receives as
+  /// [`CloseCode::NoStatusRcvd`].
+  fn is_bodyless(&self) -> bool {
+    self.code == CloseCode::NoStatusRcvd && self.reason.is_none()
+  }
+
+  /// The close frame answering this one, echoing its code if it may be sent.
+  #[cfg(any(feature = "tokio", feature = "blocking"))]
+  pub(crate) fn reply(&self) -> Self {
+    let code = if self.code.is_send_allowed() {
+      self.code
+    } else {
+      CloseCode::Normal
+    };
+
+    Self::new(code, None)
+  }
+
   pub(crate) fn encode(&self) -> WSocketResult<Vec<u8>> {
+    if self.is_bodyless() {
+      return Ok(Vec::new());
+    }
+
     if !self.code.is_send_allowed() {
-      todo!("Not implemented");
+      return Err(WSocketError::InvalidCloseCode(self.code as u16));
     }
 
     if let Some(reason) = &self.reason {
       let mut buf = Vec::with_capacity(2 + reason.len());
-      buf.copy_from_slice(&(self.code as u16).to_be_bytes());
-      buf.copy_from_slice(reason.as_bytes());
+      buf.extend_from_slice(&(self.code as u16).to_be_bytes());
+      buf.extend_from_slice(reason.as_bytes());
       Ok(buf)
     } else {
       let mut buf = Vec::with_capacity(2);
-      buf.copy_from_slice(&(self.code as u16).to_be_bytes());
+      buf.extend_from_slice(&(self.code as u16).to_be_bytes());
       Ok(buf)
     }
   }
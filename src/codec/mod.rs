@@ -0,0 +1,140 @@
+use bytes::{Buf, BytesMut};
+use tokio_util::codec::{Decoder, Encoder};
+
+use crate::frame::{apply_mask, Frame, FrameHeader};
+use crate::{Masking, Message, OwnedFrame, OwnedMessage, Role, WSocketError};
+
+#[cfg(test)]
+mod test;
+
+/// Frames WebSocket messages for [`Framed`](tokio_util::codec::Framed) and friends, without
+/// any of the connection handling of [`WebSocket`](crate::WebSocket).
+///
+/// Decodes single [`OwnedFrame`]s, so fragmented messages and control frames are left to the
+/// caller. Encodes [`OwnedFrame`]s, [`Frame`]s, [`OwnedMessage`]s and [`Message`]s, masking them
+/// if acting as [`Role::Client`].
+pub struct WebSocketCodec {
+  max_payload_len: usize,
+  role: Role,
+  masking: Masking,
+  allowed_rsv: u8,
+}
+
+impl WebSocketCodec {
+  #[inline]
+  pub fn server(max_payload_len: usize) -> Self {
+    Self {
+      max_payload_len,
+      role: Role::Server,
+      masking: Masking::Strict,
+      allowed_rsv: 0,
+    }
+  }
+
+  #[inline]
+  #[cfg(feature = "client")]
+  pub fn client(max_payload_len: usize) -> Self {
+    Self {
+      max_payload_len,
+      role: Role::Client,
+      masking: Masking::Strict,
+      allowed_rsv: 0,
+    }
+  }
+
+  #[inline]
+  pub fn with_masking(mut self, masking: Masking) -> Self {
+    self.masking = masking;
+    self
+  }
+
+  /// Reserved bits, that may be set in decoded frames, e.g. `0b100` to allow `RSV1` if an
+  /// extension using it has been negotiated. All other reserved bits have to be `0`.
+  #[inline]
+  pub fn with_allowed_rsv(mut self, allowed_rsv: u8) -> Self {
+    self.allowed_rsv = allowed_rsv & 0b111;
+    self
+  }
+
+  fn encode_frame(&self, frame: Frame<'_>, dst: &mut BytesMut) -> Result<(), WSocketError> {
+    frame.check_control()?;
+
+    if frame.data.len() > self.max_payload_len {
+      return Err(WSocketError::PayloadTooLarge);
+    }
+
+    frame.encode_into(dst, self.role.mask());
+
+    Ok(())
+  }
+}
+
+impl Decoder for WebSocketCodec {
+  type Item = OwnedFrame;
+  type Error = WSocketError;
+
+  fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+    let Some(header) = FrameHeader::parse(
+      src,
+      self.max_payload_len,
+      self.masking.expect_masked(self.role),
+      self.allowed_rsv,
+    )?
+    else {
+      return Ok(None);
+    };
+
+    let header_len = FrameHeader::encoded_len(src[1]);
+
+    if src.len() < header_len + header.len {
+      src.reserve(header_len + header.len - src.len());
+      return Ok(None);
+    }
+
+    src.advance(header_len);
+    let mut data = src.split_to(header.len);
+
+    if let Some(mask) = header.mask {
+      apply_mask(&mut data, mask);
+    }
+
+    Ok(Some(OwnedFrame {
+      fin: header.fin,
+      rsv: header.rsv,
+      opcode: header.opcode,
+      data: data.to_vec(),
+    }))
+  }
+}
+
+impl Encoder<OwnedFrame> for WebSocketCodec {
+  type Error = WSocketError;
+
+  fn encode(&mut self, item: OwnedFrame, dst: &mut BytesMut) -> Result<(), Self::Error> {
+    self.encode_frame(item.as_frame(), dst)
+  }
+}
+
+impl Encoder<Frame<'_>> for WebSocketCodec {
+  type Error = WSocketError;
+
+  fn encode(&mut self, item: Frame<'_>, dst: &mut BytesMut) -> Result<(), Self::Error> {
+    self.encode_frame(item, dst)
+  }
+}
+
+impl Encoder<OwnedMessage> for WebSocketCodec {
+  type Error = WSocketError;
+
+  fn encode(&mut self, item: OwnedMessage, dst: &mut BytesMut) -> Result<(), Self::Error> {
+    self.encode_frame(Frame::from(item.as_message()), dst)
+  }
+}
+
+impl Encoder<Message<'_>> for WebSocketCodec {
+  type Error = WSocketError;
+
+  fn encode(&mut self, item: Message<'_>, dst: &mut BytesMut) -> Result<(), Self::Error> {
+    self.encode_frame(Frame::from(item), dst)
+  }
+}
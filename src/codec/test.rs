@@ -0,0 +1,96 @@
+use bytes::BytesMut;
+use tokio_util::codec::{Decoder, Encoder};
+
+use crate::{Masking, Message, OpCode, OwnedFrame, WSocketError, WSocketResult, WebSocketCodec};
+
+#[test]
+fn test_decode_masked_frame_in_pieces() -> WSocketResult<()> {
+  let input = [
+    0x81, 0x85, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d, 0x51, 0x58,
+  ];
+  let mut codec = WebSocketCodec::server(1024);
+  let mut buf = BytesMut::new();
+
+  for byte in &input[..input.len() - 1] {
+    buf.extend_from_slice(&[*byte]);
+    assert_eq!(codec.decode(&mut buf)?, None);
+  }
+
+  buf.extend_from_slice(&input[input.len() - 1..]);
+  assert_eq!(
+    codec.decode(&mut buf)?,
+    Some(OwnedFrame {
+      fin: true,
+      rsv: 0,
+      opcode: OpCode::Text,
+      data: b"Hello".to_vec(),
+    })
+  );
+  assert!(buf.is_empty());
+
+  Ok(())
+}
+
+#[test]
+fn test_decode_rejects_unmasked_frame() {
+  let mut codec = WebSocketCodec::server(1024);
+  let mut buf = BytesMut::from(&[0x81, 0x05, 0x48, 0x65, 0x6c, 0x6c, 0x6f][..]);
+
+  assert!(matches!(
+    codec.decode(&mut buf),
+    Err(WSocketError::FrameMustBeMasked)
+  ));
+}
+
+#[test]
+fn test_decode_payload_too_large() {
+  let mut codec = WebSocketCodec::server(4).with_masking(Masking::Lenient);
+  let mut buf = BytesMut::from(&[0x81, 0x05][..]);
+
+  assert!(matches!(
+    codec.decode(&mut buf),
+    Err(WSocketError::PayloadTooLarge)
+  ));
+}
+
+#[test]
+fn test_decode_allowed_rsv() -> WSocketResult<()> {
+  let input = [0xc1, 0x05, 0x48, 0x65, 0x6c, 0x6c, 0x6f];
+
+  let mut codec = WebSocketCodec::server(1024).with_masking(Masking::Lenient);
+  assert!(matches!(
+    codec.decode(&mut BytesMut::from(&input[..])),
+    Err(WSocketError::ReserveBitMustBeNull)
+  ));
+
+  let mut codec = codec.with_allowed_rsv(0b100);
+  let frame = codec.decode(&mut BytesMut::from(&input[..]))?.unwrap();
+  assert_eq!(frame.rsv, 0b100);
+  assert_eq!(frame.data, b"Hello");
+
+  Ok(())
+}
+
+#[test]
+fn test_encode_roundtrip() -> WSocketResult<()> {
+  let mut codec = WebSocketCodec::server(1024).with_masking(Masking::Lenient);
+  let mut buf = BytesMut::new();
+
+  codec.encode(Message::Binary(b"Hello"), &mut buf)?;
+  let frame = OwnedFrame {
+    fin: false,
+    rsv: 0b100,
+    opcode: OpCode::Text,
+    data: b"Hel".to_vec(),
+  };
+  codec.encode(frame.clone(), &mut buf)?;
+
+  assert_eq!(&buf[..7], [0x82, 0x05, 0x48, 0x65, 0x6c, 0x6c, 0x6f]);
+
+  let mut codec = codec.with_allowed_rsv(0b100);
+  assert_eq!(codec.decode(&mut buf)?.unwrap().data, b"Hello");
+  assert_eq!(codec.decode(&mut buf)?, Some(frame));
+  assert_eq!(codec.decode(&mut buf)?, None);
+
+  Ok(())
+}
@@ -0,0 +1,73 @@
+use std::io::{IoSlice, Result};
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+
+use tokio::io::ReadBuf;
+
+#[cfg(test)]
+mod test;
+
+/// Adapts a [`futures_io::AsyncRead`] / [`futures_io::AsyncWrite`] stream, like the ones of smol
+/// or async-std, so it can be used with [`WebSocket`](crate::WebSocket).
+#[derive(Debug)]
+pub struct FuturesIo<T> {
+  inner: T,
+}
+
+impl<T> FuturesIo<T> {
+  pub fn new(inner: T) -> Self {
+    Self { inner }
+  }
+
+  pub fn get_ref(&self) -> &T {
+    &self.inner
+  }
+
+  pub fn get_mut(&mut self) -> &mut T {
+    &mut self.inner
+  }
+
+  pub fn into_inner(self) -> T {
+    self.inner
+  }
+}
+
+impl<T: futures_io::AsyncRead + Unpin> tokio::io::AsyncRead for FuturesIo<T> {
+  fn poll_read(
+    mut self: Pin<&mut Self>,
+    cx: &mut Context<'_>,
+    buf: &mut ReadBuf<'_>,
+  ) -> Poll<Result<()>> {
+    let read = ready!(Pin::new(&mut self.inner).poll_read(cx, buf.initialize_unfilled()))?;
+    buf.advance(read);
+    Poll::Ready(Ok(()))
+  }
+}
+
+impl<T: futures_io::AsyncWrite + Unpin> tokio::io::AsyncWrite for FuturesIo<T> {
+  fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<Result<usize>> {
+    Pin::new(&mut self.inner).poll_write(cx, buf)
+  }
+
+  fn poll_write_vectored(
+    mut self: Pin<&mut Self>,
+    cx: &mut Context<'_>,
+    bufs: &[IoSlice<'_>],
+  ) -> Poll<Result<usize>> {
+    Pin::new(&mut self.inner).poll_write_vectored(cx, bufs)
+  }
+
+  /// `futures_io::AsyncWrite` has no way to tell, and its default `poll_write_vectored` only
+  /// writes the first buffer, so small frames are better coalesced before writing them.
+  fn is_write_vectored(&self) -> bool {
+    false
+  }
+
+  fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+    Pin::new(&mut self.inner).poll_flush(cx)
+  }
+
+  fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+    Pin::new(&mut self.inner).poll_close(cx)
+  }
+}
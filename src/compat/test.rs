@@ -0,0 +1,42 @@
+use futures_util::io::Cursor;
+
+use crate::{FuturesIo, Message, OwnedMessage, WSocketResult, WebSocket, WebSocketConfig};
+
+#[tokio::test]
+async fn test_recv_over_futures_io() -> WSocketResult<()> {
+  let input = [
+    0x89, 0x85, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d, 0x51, 0x58,
+  ];
+  let mut ws = WebSocket::server(
+    FuturesIo::new(Cursor::new(input.to_vec())),
+    WebSocketConfig::default(),
+  );
+
+  let message = ws.recv_owned().await?;
+  assert_eq!(message, OwnedMessage::Ping(b"Hello".to_vec()));
+
+  Ok(())
+}
+
+#[tokio::test]
+async fn test_send_over_futures_io() -> WSocketResult<()> {
+  let mut output = Vec::new();
+  let mut ws = WebSocket::server(
+    FuturesIo::new(Cursor::new(&mut output)),
+    WebSocketConfig::default(),
+  );
+
+  ws.send(Message::Binary(b"Hello")).await?;
+  drop(ws);
+
+  assert_eq!(output, [0x82, 0x05, 0x48, 0x65, 0x6c, 0x6c, 0x6f]);
+
+  Ok(())
+}
+
+#[test]
+fn test_write_vectored_is_not_assumed() {
+  let io = FuturesIo::new(Cursor::new(Vec::new()));
+
+  assert!(!tokio::io::AsyncWrite::is_write_vectored(&io));
+}
@@ -0,0 +1,75 @@
+use std::time::Duration;
+
+use crate::protocol::DEFAULT_READ_BUFFER_LEN;
+use crate::{Limits, Masking, Role};
+
+#[cfg(all(test, feature = "serde"))]
+mod test;
+
+const DEFAULT_WRITE_HIGH_WATER_MARK: usize = 128 * 1024;
+
+/// All options of a [`WebSocket`](crate::WebSocket), accepted by
+/// [`WebSocket::server`](crate::WebSocket::server), [`WebSocket::client`](crate::WebSocket::client),
+/// `handshake` and `upgrade`, as well as their blocking counterparts.
+///
+/// With the `serde` feature it can be loaded from a configuration file. Missing fields keep their
+/// default and durations are written like `"30s"` or `"500ms"`.
+#[derive(Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(
+  feature = "serde",
+  derive(serde::Serialize, serde::Deserialize),
+  serde(default)
+)]
+pub struct WebSocketConfig {
+  /// Only used by [`WebSocket::from_config`](crate::WebSocket::from_config) and its blocking
+  /// counterpart, all other constructors imply the role.
+  pub role: Role,
+  pub masking: Masking,
+  /// Limits for received frames and messages, see [`Protocol::with_read_limits`](crate::Protocol::with_read_limits).
+  pub read_limits: Limits,
+  /// Limits for sent frames and messages, see [`Protocol::with_write_limits`](crate::Protocol::with_write_limits).
+  pub write_limits: Limits,
+  /// How much is read from the io at once, see
+  /// [`Protocol::with_read_buffer_len`](crate::Protocol::with_read_buffer_len).
+  pub read_buffer_len: usize,
+  /// Amount of bytes fed messages may occupy in the write buffer, before it is written.
+  pub write_high_water_mark: usize,
+  /// See [`WebSocket::with_write_timeout`](crate::WebSocket::with_write_timeout).
+  #[cfg_attr(feature = "serde", serde(with = "humantime_serde"))]
+  pub write_timeout: Option<Duration>,
+  /// See [`WebSocket::with_frame_timeout`](crate::WebSocket::with_frame_timeout).
+  #[cfg_attr(feature = "serde", serde(with = "humantime_serde"))]
+  pub frame_timeout: Option<Duration>,
+  /// See [`WebSocket::with_idle_timeout`](crate::WebSocket::with_idle_timeout).
+  #[cfg_attr(feature = "serde", serde(with = "humantime_serde"))]
+  pub idle_timeout: Option<Duration>,
+  /// See [`WebSocket::with_close_timeout`](crate::WebSocket::with_close_timeout).
+  #[cfg_attr(feature = "serde", serde(with = "humantime_serde"))]
+  pub close_timeout: Option<Duration>,
+  /// See [`WebSocket::with_keepalive`](crate::WebSocket::with_keepalive).
+  #[cfg_attr(feature = "serde", serde(with = "humantime_serde"))]
+  pub keepalive: Option<Duration>,
+  /// See [`WebSocket::with_auto_pong`](crate::WebSocket::with_auto_pong).
+  pub auto_pong: bool,
+}
+
+/// A server with strict masking and the default [`Limits`], answering pings. All timeouts are
+/// disabled, so no tokio timer is required.
+impl Default for WebSocketConfig {
+  fn default() -> Self {
+    Self {
+      role: Role::Server,
+      masking: Masking::Strict,
+      read_limits: Limits::default(),
+      write_limits: Limits::default(),
+      read_buffer_len: DEFAULT_READ_BUFFER_LEN,
+      write_high_water_mark: DEFAULT_WRITE_HIGH_WATER_MARK,
+      write_timeout: None,
+      frame_timeout: None,
+      idle_timeout: None,
+      close_timeout: None,
+      keepalive: None,
+      auto_pong: true,
+    }
+  }
+}
@@ -0,0 +1,45 @@
+use std::time::Duration;
+
+use crate::{Limits, Masking, WebSocketConfig};
+
+#[test]
+fn test_deserialize_partial_config() {
+  let config: WebSocketConfig = serde_json::from_str(
+    r#"{
+      "masking": "lenient",
+      "read_limits": { "max_message_len": 1024 },
+      "idle_timeout": "30s",
+      "keepalive": "500ms"
+    }"#,
+  )
+  .unwrap();
+
+  assert_eq!(
+    config,
+    WebSocketConfig {
+      masking: Masking::Lenient,
+      read_limits: Limits {
+        max_message_len: 1024,
+        ..Limits::default()
+      },
+      idle_timeout: Some(Duration::from_secs(30)),
+      keepalive: Some(Duration::from_millis(500)),
+      ..WebSocketConfig::default()
+    }
+  );
+}
+
+#[test]
+fn test_serialize_roundtrip() {
+  let config = WebSocketConfig {
+    write_timeout: Some(Duration::from_secs(5)),
+    auto_pong: false,
+    ..WebSocketConfig::default()
+  };
+
+  let json = serde_json::to_string(&config).unwrap();
+  assert_eq!(
+    serde_json::from_str::<WebSocketConfig>(&json).unwrap(),
+    config
+  );
+}
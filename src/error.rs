@@ -1,10 +1,13 @@
+#[cfg(feature = "alloc")]
+use alloc::string::FromUtf8Error;
+#[cfg(feature = "std")]
 use std::io;
-use std::string::FromUtf8Error;
 
 #[cfg(all(feature = "handshake", feature = "client"))]
 use hyper::StatusCode;
 use thiserror::Error;
 
+#[cfg(feature = "alloc")]
 use crate::Close;
 use crate::CloseCode;
 
@@ -18,12 +21,27 @@ pub enum WSocketError {
   UnknownCloseCode(u16),
   #[error("reserve bit must be `0`")]
   ReserveBitMustBeNull,
+  #[error("frame must be masked")]
+  FrameMustBeMasked,
+  #[error("frame must not be masked")]
+  FrameMustNotBeMasked,
   #[error("control frame must not be fragmented")]
   ControlFrameMustNotBeFragmented,
   #[error("control frame must have a payload length of 125 bytes or less")]
   ControlFrameMustHaveAPayloadLengthOf125BytesOrLess,
+  #[error("most significant bit of the payload length must be `0`")]
+  PayloadLengthMostSignificantBitMustBeNull,
   #[error("payload too large")]
   PayloadTooLarge,
+  #[error("buffer too small, payload requires {0} bytes")]
+  BufferTooSmall(usize),
+  #[error("timed out writing to the peer")]
+  WriteTimeout,
+  #[error("timed out receiving the rest of a frame")]
+  FrameTimeout,
+  #[error("timed out waiting for the next frame")]
+  IdleTimeout,
+  #[cfg(feature = "std")]
   #[error("io error")]
   Io(
     #[source]
@@ -32,12 +50,22 @@ pub enum WSocketError {
   ),
   #[error("not connected")]
   NotConnected,
+  #[error("send queue full")]
+  QueueFull,
+  #[cfg(feature = "alloc")]
   #[error("connection closed")]
   ConnectionClosed(Close),
+  #[error("too many fragments")]
+  TooManyFragments,
+  #[error("continuation frame without a preceding fragment")]
+  UnexpectedContinuationFrame,
+  #[error("expected continuation frame")]
+  ExpectedContinuationFrame,
   #[error("framed messages are not supported")]
   FramedMessagesAreNotSupported,
   #[error("text frames are not supported")]
   TextFramesAreNotSupported,
+  #[cfg(feature = "alloc")]
   #[error("invalid utf8")]
   InvalidUtf8(
     #[source]
@@ -52,6 +80,10 @@ pub enum WSocketError {
     actual: StatusCode,
     expected: StatusCode,
   },
+  #[error("invalid http response")]
+  InvalidHttpResponse,
+  #[error("invalid response status code `{0}`, expected 101")]
+  InvalidResponseStatus(u16),
   #[error("invalid websocket http upgrade header")]
   InvalidUpgradeHeader,
   #[error("invalid websocket http connection header")]
@@ -63,6 +95,8 @@ pub enum WSocketError {
     #[from]
     hyper::Error,
   ),
+  #[error("invalid sec websocket accept")]
+  InvalidSecWebSocketAccept,
   #[error("missing sec web socket key")]
   MissingSecWebSocketKey,
   #[error("unsupported sec websocket version")]
@@ -70,31 +104,60 @@ pub enum WSocketError {
 }
 
 impl WSocketError {
+  /// Whether the io failed or stalled, so there is no point in sending a close frame.
+  #[cfg(any(feature = "tokio", feature = "blocking"))]
   pub(crate) fn is_io_error(&self) -> bool {
-    matches!(self, WSocketError::Io(_))
+    matches!(self, WSocketError::Io(_) | WSocketError::WriteTimeout)
   }
 
-  pub(crate) fn close_code(&self) -> Option<CloseCode> {
+  /// The close reported to the peer and the other half, when the connection fails with this error.
+  #[cfg(any(feature = "tokio", feature = "blocking"))]
+  pub(crate) fn to_close(&self) -> Close {
+    Close::new(
+      self.close_code().unwrap_or(CloseCode::InternalError),
+      Some(format!("{}", self)),
+    )
+  }
+
+  /// The close code reported to the peer, when the connection fails with this error.
+  pub fn close_code(&self) -> Option<CloseCode> {
     match self {
       Self::UnknownOpCode(_) => Some(CloseCode::ProtocolError),
       Self::UnknownCloseCode(_) => Some(CloseCode::ProtocolError),
       Self::ReserveBitMustBeNull => Some(CloseCode::Unsupported),
+      Self::FrameMustBeMasked => Some(CloseCode::ProtocolError),
+      Self::FrameMustNotBeMasked => Some(CloseCode::ProtocolError),
       Self::ControlFrameMustNotBeFragmented => Some(CloseCode::Unsupported),
       Self::ControlFrameMustHaveAPayloadLengthOf125BytesOrLess => Some(CloseCode::ProtocolError),
+      Self::PayloadLengthMostSignificantBitMustBeNull => Some(CloseCode::ProtocolError),
       Self::PayloadTooLarge => Some(CloseCode::MessageTooBig),
+      Self::BufferTooSmall(_) => Some(CloseCode::MessageTooBig),
+      Self::WriteTimeout => Some(CloseCode::Abnormal),
+      Self::FrameTimeout => Some(CloseCode::PolicyViolation),
+      Self::IdleTimeout => Some(CloseCode::Away),
+      #[cfg(feature = "std")]
       Self::Io(_) => Some(CloseCode::Abnormal),
       Self::NotConnected => None,
+      Self::QueueFull => Some(CloseCode::PolicyViolation),
+      #[cfg(feature = "alloc")]
       Self::ConnectionClosed(_) => None,
+      Self::TooManyFragments => Some(CloseCode::MessageTooBig),
+      Self::UnexpectedContinuationFrame => Some(CloseCode::ProtocolError),
+      Self::ExpectedContinuationFrame => Some(CloseCode::ProtocolError),
       Self::FramedMessagesAreNotSupported => Some(CloseCode::Unsupported),
       Self::TextFramesAreNotSupported => Some(CloseCode::Unsupported),
+      #[cfg(feature = "alloc")]
       Self::InvalidUtf8(_) => Some(CloseCode::InvalidPayload),
       Self::InvalidCloseCode(_) => None,
       #[cfg(all(feature = "handshake", feature = "client"))]
       Self::InvalidStatusCode { .. } => None,
+      Self::InvalidHttpResponse => None,
+      Self::InvalidResponseStatus(_) => None,
       Self::InvalidUpgradeHeader => None,
       Self::InvalidConnectionHeader => None,
       #[cfg(any(feature = "upgrade", all(feature = "client", feature = "handshake")))]
       Self::Hyper(_) => None,
+      Self::InvalidSecWebSocketAccept => None,
       Self::MissingSecWebSocketKey => None,
       Self::UnsupportedSecWebsocketVersion => None,
     }
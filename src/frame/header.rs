@@ -0,0 +1,166 @@
+use crate::frame::{OpCode, MAX_HEADER_LEN};
+use crate::{WSocketError, WSocketResult};
+
+/// The header of a single frame, preceding its payload.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct FrameHeader {
+  pub fin: bool,
+  /// The reserved bits `RSV1`, `RSV2` and `RSV3`, `RSV1` being `0b100`.
+  pub rsv: u8,
+  pub opcode: OpCode,
+  /// The masking key, if the payload is masked.
+  pub mask: Option<[u8; 4]>,
+  /// Length of the payload.
+  pub len: usize,
+}
+
+impl FrameHeader {
+  /// Total length of an encoded header, derived from its second byte.
+  pub fn encoded_len(b2: u8) -> usize {
+    let extended_len = match b2 & 0b0111_1111 {
+      126 => 2,
+      127 => 8,
+      _ => 0,
+    };
+
+    let mask_len = if b2 & 0b1000_0000 != 0 { 4 } else { 0 };
+
+    2 + extended_len + mask_len
+  }
+
+  /// ### WebSocket Frame Header
+  /// <https://datatracker.ietf.org/doc/html/rfc6455#section-5.2>
+  ///
+  /// ```txt
+  ///  0                   1                   2                   3
+  ///  0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1
+  /// +-+-+-+-+-------+-+-------------+-------------------------------+
+  /// |F|R|R|R| opcode|M| Payload len |    Extended payload length    |
+  /// |I|S|S|S|  (4)  |A|     (7)     |             (16/64)           |
+  /// |N|V|V|V|       |S|             |   (if payload len==126/127)   |
+  /// | |1|2|3|       |K|             |                               |
+  /// +-+-+-+-+-------+-+-------------+ - - - - - - - - - - - - - - - +
+  /// |     Extended payload length continued, if payload len == 127  |
+  /// + - - - - - - - - - - - - - - - +-------------------------------+
+  /// |                               |Masking-key, if MASK set to 1  |
+  /// +-------------------------------+-------------------------------+
+  /// | Masking-key (continued)       |          Payload Data         |
+  /// +-------------------------------- - - - - - - - - - - - - - - - +
+  /// :                     Payload Data continued ...                :
+  /// + - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - +
+  /// |                     Payload Data continued ...                |
+  /// +---------------------------------------------------------------+
+  /// ```
+  ///
+  /// Returns [`None`], if `buf` doesn't contain the complete header yet, see
+  /// [`FrameHeader::encoded_len`]. Bytes following the header are ignored.
+  /// If `expect_masked` is set, frames with a different mask bit are rejected.
+  /// Reserved bits not set in `allowed_rsv` (`RSV1` being `0b100`) have to be `0`.
+  pub fn parse(
+    buf: &[u8],
+    max_payload_len: usize,
+    expect_masked: Option<bool>,
+    allowed_rsv: u8,
+  ) -> WSocketResult<Option<Self>> {
+    if buf.len() < 2 || buf.len() < Self::encoded_len(buf[1]) {
+      return Ok(None);
+    }
+
+    let b1 = buf[0];
+    let b2 = buf[1];
+
+    let fin = b1 & 0b1000_0000 != 0;
+    let rsv = (b1 & 0b0111_0000) >> 4;
+    let opcode = OpCode::try_from(b1 & 0b0000_1111)?;
+
+    let len = (b2 & 0b0111_1111) as usize;
+    let masked = b2 & 0b_1000_0000 != 0;
+
+    if rsv & !allowed_rsv != 0 {
+      return Err(WSocketError::ReserveBitMustBeNull);
+    }
+
+    match expect_masked {
+      Some(true) if !masked => return Err(WSocketError::FrameMustBeMasked),
+      Some(false) if masked => return Err(WSocketError::FrameMustNotBeMasked),
+      _ => {}
+    }
+
+    let (len, rest) = match opcode {
+      OpCode::Continuation | OpCode::Text | OpCode::Binary => match len {
+        126 => (u16::from_be_bytes([buf[2], buf[3]]) as usize, &buf[4..]),
+        127 => {
+          let len = u64::from_be_bytes(buf[2..10].try_into().unwrap());
+
+          // https://datatracker.ietf.org/doc/html/rfc6455#section-5.2
+          if len >> 63 != 0 {
+            return Err(WSocketError::PayloadLengthMostSignificantBitMustBeNull);
+          }
+
+          let len = usize::try_from(len).map_err(|_| WSocketError::PayloadTooLarge)?;
+          (len, &buf[10..])
+        }
+        len => (len, &buf[2..]),
+      },
+      OpCode::Close | OpCode::Ping | OpCode::Pong => {
+        if !fin {
+          return Err(WSocketError::ControlFrameMustNotBeFragmented);
+        }
+
+        if len > 125 {
+          return Err(WSocketError::ControlFrameMustHaveAPayloadLengthOf125BytesOrLess);
+        }
+
+        (len, &buf[2..])
+      }
+    };
+
+    if len > max_payload_len {
+      return Err(WSocketError::PayloadTooLarge);
+    }
+
+    let mask = if masked {
+      Some([rest[0], rest[1], rest[2], rest[3]])
+    } else {
+      None
+    };
+
+    Ok(Some(Self {
+      fin,
+      rsv,
+      opcode,
+      mask,
+      len,
+    }))
+  }
+
+  /// Encodes the header into a stack buffer, returning it along with the amount of bytes used.
+  pub fn encode(&self) -> ([u8; MAX_HEADER_LEN], usize) {
+    let mut header = [0u8; MAX_HEADER_LEN];
+    let mask_bit = if self.mask.is_some() { 0x80 } else { 0 };
+
+    header[0] = ((self.fin as u8) << 7) | ((self.rsv & 0b111) << 4) | self.opcode as u8;
+
+    let len = self.len;
+
+    let mut header_len = if len < 126 {
+      header[1] = mask_bit | len as u8;
+      2
+    } else if len < 65536 {
+      header[1] = mask_bit | 126;
+      header[2..4].copy_from_slice(&(len as u16).to_be_bytes());
+      4
+    } else {
+      header[1] = mask_bit | 127;
+      header[2..10].copy_from_slice(&(len as u64).to_be_bytes());
+      10
+    };
+
+    if let Some(mask) = self.mask {
+      header[header_len..header_len + 4].copy_from_slice(&mask);
+      header_len += 4;
+    }
+
+    (header, header_len)
+  }
+}
@@ -0,0 +1,116 @@
+/// Masks or unmasks `buf` in place using the masking key `mask`.
+/// <https://datatracker.ietf.org/doc/html/rfc6455#section-5.3>
+///
+/// Uses AVX2 or SSE2 on `x86_64` and NEON on `aarch64`. On every other architecture the payload is
+/// processed eight bytes at a time. Without `std`, AVX2 is only used if it is enabled at compile
+/// time.
+#[inline]
+pub fn apply_mask(buf: &mut [u8], mask: [u8; 4]) {
+  #[cfg(target_arch = "x86_64")]
+  if x86_64::has_avx2() {
+    // SAFETY: availability of avx2 has been checked at runtime
+    unsafe { x86_64::apply_mask_avx2(buf, mask) }
+  } else {
+    // SAFETY: sse2 is part of the x86_64 baseline
+    unsafe { x86_64::apply_mask_sse2(buf, mask) }
+  }
+
+  #[cfg(target_arch = "aarch64")]
+  // SAFETY: neon is part of the aarch64 baseline
+  unsafe {
+    aarch64::apply_mask_neon(buf, mask)
+  }
+
+  #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+  apply_mask_word(buf, mask)
+}
+
+/// Portable fallback, xor-ing eight bytes at a time. The trailing bytes are masked one by one.
+#[inline]
+pub(crate) fn apply_mask_word(buf: &mut [u8], mask: [u8; 4]) {
+  let [m1, m2, m3, m4] = mask;
+  let word_mask = u64::from_ne_bytes([m1, m2, m3, m4, m1, m2, m3, m4]);
+
+  let mut chunks = buf.chunks_exact_mut(8);
+
+  for chunk in &mut chunks {
+    let word = u64::from_ne_bytes(chunk.try_into().unwrap()) ^ word_mask;
+    chunk.copy_from_slice(&word.to_ne_bytes());
+  }
+
+  // every chunk is a multiple of four bytes long, so the remainder starts at mask index 0
+  for (idx, byte) in chunks.into_remainder().iter_mut().enumerate() {
+    *byte ^= mask[idx & 3];
+  }
+}
+
+#[cfg(target_arch = "x86_64")]
+mod x86_64 {
+  use core::arch::x86_64::{
+    __m128i, __m256i, _mm256_loadu_si256, _mm256_set1_epi32, _mm256_storeu_si256, _mm256_xor_si256,
+    _mm_loadu_si128, _mm_set1_epi32, _mm_storeu_si128, _mm_xor_si128,
+  };
+
+  use super::apply_mask_word;
+
+  #[cfg(feature = "std")]
+  #[inline]
+  pub(super) fn has_avx2() -> bool {
+    is_x86_feature_detected!("avx2")
+  }
+
+  #[cfg(not(feature = "std"))]
+  #[inline]
+  pub(super) fn has_avx2() -> bool {
+    cfg!(target_feature = "avx2")
+  }
+
+  #[target_feature(enable = "avx2")]
+  pub(super) unsafe fn apply_mask_avx2(buf: &mut [u8], mask: [u8; 4]) {
+    let vec_mask = _mm256_set1_epi32(i32::from_ne_bytes(mask));
+
+    let mut chunks = buf.chunks_exact_mut(32);
+
+    for chunk in &mut chunks {
+      let ptr = chunk.as_mut_ptr() as *mut __m256i;
+      _mm256_storeu_si256(ptr, _mm256_xor_si256(_mm256_loadu_si256(ptr), vec_mask));
+    }
+
+    apply_mask_word(chunks.into_remainder(), mask);
+  }
+
+  #[target_feature(enable = "sse2")]
+  pub(super) unsafe fn apply_mask_sse2(buf: &mut [u8], mask: [u8; 4]) {
+    let vec_mask = _mm_set1_epi32(i32::from_ne_bytes(mask));
+
+    let mut chunks = buf.chunks_exact_mut(16);
+
+    for chunk in &mut chunks {
+      let ptr = chunk.as_mut_ptr() as *mut __m128i;
+      _mm_storeu_si128(ptr, _mm_xor_si128(_mm_loadu_si128(ptr), vec_mask));
+    }
+
+    apply_mask_word(chunks.into_remainder(), mask);
+  }
+}
+
+#[cfg(target_arch = "aarch64")]
+mod aarch64 {
+  use core::arch::aarch64::{vdupq_n_u32, veorq_u8, vld1q_u8, vreinterpretq_u8_u32, vst1q_u8};
+
+  use super::apply_mask_word;
+
+  #[target_feature(enable = "neon")]
+  pub(super) unsafe fn apply_mask_neon(buf: &mut [u8], mask: [u8; 4]) {
+    let vec_mask = vreinterpretq_u8_u32(vdupq_n_u32(u32::from_ne_bytes(mask)));
+
+    let mut chunks = buf.chunks_exact_mut(16);
+
+    for chunk in &mut chunks {
+      let ptr = chunk.as_mut_ptr();
+      vst1q_u8(ptr, veorq_u8(vld1q_u8(ptr), vec_mask));
+    }
+
+    apply_mask_word(chunks.into_remainder(), mask);
+  }
+}
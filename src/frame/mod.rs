@@ -1,183 +1,205 @@
-use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+#[cfg(feature = "alloc")]
+use alloc::vec::Vec;
 
-pub(crate) use opcode::OpCode;
+pub use header::FrameHeader;
+pub use mask::apply_mask;
+pub use opcode::OpCode;
 
-use crate::error::WSocketResult;
-use crate::WSocketError;
+use crate::Message;
+#[cfg(feature = "alloc")]
+use crate::{Close, WSocketError};
 
+mod header;
+mod mask;
 mod opcode;
-#[cfg(test)]
+#[cfg(all(test, feature = "tokio"))]
 mod test;
+#[cfg(feature = "tokio")]
+mod write;
+
+#[cfg(feature = "tokio")]
+pub(crate) use write::FrameWrite;
+
+/// Two bytes, followed by an up to eight bytes long extended payload length and the masking key.
+pub const MAX_HEADER_LEN: usize = 14;
+
+/// A single frame borrowing its unmasked payload.
+///
+/// Frames can be sent as is using [`WebSocket::send_frame`](crate::WebSocket::send_frame), or
+/// encoded with an explicit masking key using [`encode`](Self::encode).
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Frame<'a> {
+  pub fin: bool,
+  /// The reserved bits `RSV1`, `RSV2` and `RSV3`, `RSV1` being `0b100`.
+  pub rsv: u8,
+  pub opcode: OpCode,
+  pub data: &'a [u8],
+}
 
-pub(crate) struct Frame<'a> {
-  pub(crate) fin: bool,
-  pub(crate) opcode: OpCode,
-  pub(crate) data: &'a [u8],
+/// A single frame with its unmasked payload, as produced by the
+/// [`WebSocketCodec`](crate::WebSocketCodec).
+#[cfg(feature = "alloc")]
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct OwnedFrame {
+  pub fin: bool,
+  /// The reserved bits `RSV1`, `RSV2` and `RSV3`, `RSV1` being `0b100`.
+  pub rsv: u8,
+  pub opcode: OpCode,
+  pub data: Vec<u8>,
 }
 
 impl<'a> Frame<'a> {
   #[inline]
-  pub(crate) const fn new(fin: bool, opcode: OpCode, data: &'a [u8]) -> Self {
-    Self { fin, opcode, data }
+  pub const fn new(fin: bool, opcode: OpCode, data: &'a [u8]) -> Self {
+    Self {
+      fin,
+      rsv: 0,
+      opcode,
+      data,
+    }
   }
 
-  /// ### WebSocket Frame Header
-  /// <https://datatracker.ietf.org/doc/html/rfc6455#section-5.2>
-  ///
-  /// ```txt
-  ///  0                   1                   2                   3
-  ///  0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1
-  /// +-+-+-+-+-------+-+-------------+-------------------------------+
-  /// |F|R|R|R| opcode|M| Payload len |    Extended payload length    |
-  /// |I|S|S|S|  (4)  |A|     (7)     |             (16/64)           |
-  /// |N|V|V|V|       |S|             |   (if payload len==126/127)   |
-  /// | |1|2|3|       |K|             |                               |
-  /// +-+-+-+-+-------+-+-------------+ - - - - - - - - - - - - - - - +
-  /// |     Extended payload length continued, if payload len == 127  |
-  /// + - - - - - - - - - - - - - - - +-------------------------------+
-  /// |                               |Masking-key, if MASK set to 1  |
-  /// +-------------------------------+-------------------------------+
-  /// | Masking-key (continued)       |          Payload Data         |
-  /// +-------------------------------- - - - - - - - - - - - - - - - +
-  /// :                     Payload Data continued ...                :
-  /// + - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - +
-  /// |                     Payload Data continued ...                |
-  /// +---------------------------------------------------------------+
-  /// ```
-  pub(crate) async fn read<R: Unpin + AsyncRead>(
-    read: &mut R,
-    buf: &'a mut [u8],
-    max_payload_len: usize,
-  ) -> WSocketResult<Frame<'a>> {
-    let [b1, b2] = {
-      let mut header = [0u8; 2];
-      read.read_exact(&mut header).await?;
-      header
-    };
-
-    let fin = b1 & 0b1000_0000 != 0;
-    let rsv = b1 & 0b0111_0000;
-    let opcode = OpCode::try_from(b1 & 0b0000_1111)?;
-
-    let len = (b2 & 0b0111_1111) as usize;
-    let masked = b2 & 0b_1000_0000 != 0;
-
-    if rsv != 0 {
-      return Err(WSocketError::ReserveBitMustBeNull);
-    }
+  #[inline]
+  pub const fn with_rsv(mut self, rsv: u8) -> Self {
+    self.rsv = rsv & 0b111;
+    self
+  }
 
-    let len = match opcode {
-      OpCode::Continuation | OpCode::Text | OpCode::Binary => match len {
-        126 => read.read_u16().await? as usize,
-        127 => read.read_u64().await? as usize,
-        len => len,
-      },
-      OpCode::Close | OpCode::Ping | OpCode::Pong => {
-        if !fin {
-          return Err(WSocketError::ControlFrameMustNotBeFragmented);
-        }
-
-        if len > 125 {
-          return Err(WSocketError::ControlFrameMustHaveAPayloadLengthOf125BytesOrLess);
-        }
-
-        len
-      }
-    };
-
-    if len > max_payload_len {
-      return Err(WSocketError::PayloadTooLarge);
+  /// The header preceding this frame, if it is masked using `mask`.
+  pub fn header(&self, mask: Option<[u8; 4]>) -> FrameHeader {
+    FrameHeader {
+      fin: self.fin,
+      rsv: self.rsv,
+      opcode: self.opcode,
+      mask,
+      len: self.data.len(),
     }
+  }
 
-    read_payload(read, &mut buf[..len], masked).await?;
+  /// Fails if this is a control frame, that is fragmented or exceeds 125 bytes.
+  #[cfg(feature = "alloc")]
+  pub(crate) fn check_control(&self) -> Result<(), WSocketError> {
+    if !self.opcode.is_control() {
+      return Ok(());
+    }
 
-    Ok(Self {
-      fin,
-      opcode,
-      data: &buf[..len],
-    })
-  }
+    if !self.fin {
+      return Err(WSocketError::ControlFrameMustNotBeFragmented);
+    }
 
-  pub(crate) async fn write_without_mask<W: Unpin + AsyncWrite>(
-    &self,
-    write: &mut W,
-  ) -> WSocketResult<()> {
-    self.write_header(write, 0).await?;
-    write.write_all(self.data).await?;
+    if self.data.len() > 125 {
+      return Err(WSocketError::ControlFrameMustHaveAPayloadLengthOf125BytesOrLess);
+    }
 
     Ok(())
   }
 
-  #[cfg(feature = "client")]
-  pub async fn write_with_mask<W: Unpin + AsyncWrite>(
-    &self,
-    write: &mut W,
-    mask: [u8; 4],
-  ) -> WSocketResult<()> {
-    self.write_header(write, 0x80).await?;
-    write.write_all(&mask).await?;
-
-    // TODO: Use SIMD wherever possible for best performance
-    // TODO: is it ok, that the user provided data buffer is modified?
-    // self
-    //   .data
-    //   .iter_mut()
-    //   .enumerate()
-    //   .for_each(|(idx, byte)| *byte ^= unsafe { mask.get_unchecked(idx & 3) });
-
-    for i in 0..self.data.len() {
-      // TODO: Use SIMD wherever possible for best performance
-      write
-        .write_u8(unsafe { self.data.get_unchecked(i) ^ mask.get_unchecked(i & 3) })
-        .await?
+  /// Appends header and payload to `buf`, masking the payload if a `mask` is given.
+  #[cfg(feature = "alloc")]
+  pub fn encode(&self, buf: &mut Vec<u8>, mask: Option<[u8; 4]>) {
+    self.encode_into(buf, mask);
+  }
+
+  /// Same as [`encode`](Self::encode), for any growable buffer.
+  #[cfg(feature = "alloc")]
+  pub(crate) fn encode_into<B: EncodeBuf>(&self, buf: &mut B, mask: Option<[u8; 4]>) {
+    let (header, header_len) = self.header(mask).encode();
+
+    buf.reserve(header_len + self.data.len());
+    buf.extend_from_slice(&header[..header_len]);
+
+    let start = buf.len();
+    buf.extend_from_slice(self.data);
+
+    if let Some(mask) = mask {
+      apply_mask(&mut buf[start..], mask);
     }
+  }
+}
 
-    Ok(())
+/// Buffers frames can be encoded into.
+#[cfg(feature = "alloc")]
+pub(crate) trait EncodeBuf: core::ops::DerefMut<Target = [u8]> {
+  fn reserve(&mut self, additional: usize);
+  fn extend_from_slice(&mut self, data: &[u8]);
+}
+
+#[cfg(feature = "alloc")]
+impl EncodeBuf for Vec<u8> {
+  #[inline]
+  fn reserve(&mut self, additional: usize) {
+    Vec::reserve(self, additional);
   }
 
-  async fn write_header<W: Unpin + AsyncWrite>(
-    &self,
-    write: &mut W,
-    mask_bit: u8,
-  ) -> WSocketResult<()> {
-    write
-      .write_u8(((self.fin as u8) << 7) | self.opcode as u8)
-      .await?;
-
-    let len = self.data.len();
-
-    if len < 126 {
-      write.write_u8(mask_bit | len as u8).await?;
-    } else if len < 65536 {
-      write.write_u8(mask_bit | 126).await?;
-      write.write_u16(len as u16).await?;
-    } else {
-      write.write_u8(mask_bit | 127).await?;
-      write.write_u64(len as u64).await?;
-    }
+  #[inline]
+  fn extend_from_slice(&mut self, data: &[u8]) {
+    Vec::extend_from_slice(self, data);
+  }
+}
 
-    Ok(())
+#[cfg(feature = "codec")]
+impl EncodeBuf for bytes::BytesMut {
+  #[inline]
+  fn reserve(&mut self, additional: usize) {
+    bytes::BytesMut::reserve(self, additional);
+  }
+
+  #[inline]
+  fn extend_from_slice(&mut self, data: &[u8]) {
+    bytes::BytesMut::extend_from_slice(self, data);
   }
 }
 
-async fn read_payload<R: Unpin + AsyncRead>(
-  read: &mut R,
-  buf: &mut [u8],
-  masked: bool,
-) -> WSocketResult<()> {
-  if masked {
-    let mut mask = [0u8; 4];
-    read.read_exact(&mut mask).await?;
-    read.read_exact(buf).await?;
-    // TODO: Use SIMD wherever possible for best performance
-
-    buf
-      .iter_mut()
-      .enumerate()
-      .for_each(|(idx, byte)| *byte ^= unsafe { mask.get_unchecked(idx & 3) })
-  } else {
-    read.read_exact(buf).await?;
+#[cfg(feature = "alloc")]
+impl OwnedFrame {
+  pub fn as_frame(&self) -> Frame<'_> {
+    Frame::new(self.fin, self.opcode, &self.data).with_rsv(self.rsv)
   }
+}
 
-  Ok(())
+#[cfg(feature = "alloc")]
+impl From<Frame<'_>> for OwnedFrame {
+  fn from(frame: Frame<'_>) -> Self {
+    Self {
+      fin: frame.fin,
+      rsv: frame.rsv,
+      opcode: frame.opcode,
+      data: frame.data.to_vec(),
+    }
+  }
+}
+
+impl<'a> From<Message<'a>> for Frame<'a> {
+  fn from(message: Message<'a>) -> Self {
+    match message {
+      Message::Binary(data) => Frame::new(true, OpCode::Binary, data),
+      Message::Text(text) => Frame::new(true, OpCode::Text, text.as_bytes()),
+      Message::Ping(data) => Frame::new(true, OpCode::Ping, data),
+      Message::Pong(data) => Frame::new(true, OpCode::Pong, data),
+    }
+  }
+}
+
+/// Interprets a single unfragmented frame as message.
+///
+/// A close frame is returned as [`WSocketError::ConnectionClosed`], text frames and fragmented
+/// messages are not supported yet.
+#[cfg(feature = "alloc")]
+impl<'a> TryFrom<Frame<'a>> for Message<'a> {
+  type Error = WSocketError;
+
+  fn try_from(frame: Frame<'a>) -> Result<Self, Self::Error> {
+    if !frame.fin {
+      return Err(WSocketError::FramedMessagesAreNotSupported);
+    }
+
+    match frame.opcode {
+      OpCode::Continuation => Err(WSocketError::FramedMessagesAreNotSupported),
+      OpCode::Text => Err(WSocketError::TextFramesAreNotSupported),
+      OpCode::Binary => Ok(Message::Binary(frame.data)),
+      OpCode::Close => Err(WSocketError::ConnectionClosed(Close::parse(frame.data)?)),
+      OpCode::Ping => Ok(Message::Ping(frame.data)),
+      OpCode::Pong => Ok(Message::Pong(frame.data)),
+    }
+  }
 }
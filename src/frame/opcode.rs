@@ -1,8 +1,9 @@
 use crate::WSocketError;
 
+/// <https://datatracker.ietf.org/doc/html/rfc6455#section-5.2>
 #[repr(u8)]
 #[derive(Debug, Copy, Clone, Ord, PartialOrd, Eq, PartialEq)]
-pub(crate) enum OpCode {
+pub enum OpCode {
   Continuation = 0x0,
   Text = 0x1,
   Binary = 0x2,
@@ -11,6 +12,13 @@ pub(crate) enum OpCode {
   Pong = 0xA,
 }
 
+impl OpCode {
+  /// Whether this is the opcode of a control frame (close, ping or pong).
+  pub fn is_control(&self) -> bool {
+    matches!(self, Self::Close | Self::Ping | Self::Pong)
+  }
+}
+
 impl TryFrom<u8> for OpCode {
   type Error = WSocketError;
 
@@ -1,17 +1,54 @@
-use std::io::Cursor;
+use std::future::poll_fn;
+use std::io::{self, Cursor, IoSlice};
+use std::pin::Pin;
+use std::task::{Context, Poll};
 
-use crate::frame::{Frame, OpCode};
-use crate::WSocketResult;
+use tokio::io::AsyncWrite;
+
+use crate::frame::mask::apply_mask_word;
+use crate::frame::{apply_mask, Frame, FrameHeader, FrameWrite, OpCode};
+use crate::{
+  Close, CloseCode, Limits, Masking, Message, OwnedFrame, Protocol, WSocketError, WSocketResult,
+  WebSocket, WebSocketConfig,
+};
+
+/// Decodes a single frame from `input`, accepting masked as well as unmasked frames.
+fn read_frame(input: &[u8], max_payload_len: usize) -> WSocketResult<OwnedFrame> {
+  let mut protocol = Protocol::server(max_payload_len).with_masking(Masking::Lenient);
+  protocol.receive(input);
+
+  let (header, payload) = protocol
+    .decode()?
+    .ok_or(io::Error::from(io::ErrorKind::UnexpectedEof))?;
+
+  Ok(OwnedFrame {
+    fin: header.fin,
+    rsv: header.rsv,
+    opcode: header.opcode,
+    data: protocol.payload(payload).to_vec(),
+  })
+}
+
+/// Receives a single message from `input` into a buffer of `size` bytes.
+async fn recv_message(input: &[u8], size: usize, max_payload_len: usize) -> WSocketResult<()> {
+  let config = WebSocketConfig {
+    read_limits: Limits::new(max_payload_len),
+    ..WebSocketConfig::default()
+  };
+  let mut ws =
+    WebSocket::server(Cursor::new(input.to_vec()), config).with_masking(Masking::Lenient);
+
+  let mut buf = vec![0u8; size];
+  ws.recv(&mut buf).await?;
+  Ok(())
+}
 
 macro_rules! test_read_frame {
   ($($name:ident: ($input:expr, $size:expr, $fin:expr, $opcode:expr, $data:expr),)*) => {
     $(
-      #[tokio::test]
-      async fn $name() -> WSocketResult<()> {
-        let mut buf = [0u8; $size];
-
-        let mut read = Cursor::new($input);
-        let frame = Frame::read(&mut read, &mut buf, $size).await?;
+      #[test]
+      fn $name() -> WSocketResult<()> {
+        let frame = read_frame(&$input[..], $size)?;
 
         assert_eq!(frame.fin, $fin);
         assert_eq!(frame.opcode, $opcode);
@@ -82,3 +119,297 @@ test_read_frame! {
     include_bytes!("../test/frame_65536_out.bin")
   ),
 }
+
+macro_rules! test_read_frame_error {
+  ($($name:ident: ($input:expr, $size:expr, $max_payload_len:expr, $error:pat),)*) => {
+    $(
+      #[tokio::test]
+      async fn $name() {
+        let result = recv_message(&$input[..], $size, $max_payload_len).await;
+
+        assert!(matches!(result, Err($error)));
+      }
+    )*
+  }
+}
+
+test_read_frame_error! {
+  test_read_frame_buffer_smaller_than_payload: (
+    [0x82, 0x05, 0x48, 0x65, 0x6c, 0x6c, 0x6f],
+    4,
+    5,
+    WSocketError::BufferTooSmall(5)
+  ),
+  test_read_frame_16bit_len_buffer_smaller_than_payload: (
+    [&[0x82, 0x7e, 0x01, 0x2c][..], &[0u8; 300]].concat(),
+    256,
+    1024,
+    WSocketError::BufferTooSmall(300)
+  ),
+  test_read_frame_64bit_len_buffer_smaller_than_payload: (
+    [&[0x82, 0x7f, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00][..], &[0u8; 65536]].concat(),
+    256,
+    usize::MAX,
+    WSocketError::BufferTooSmall(65536)
+  ),
+  test_read_frame_16bit_len_too_large: (
+    [0x82, 0x7e, 0x01, 0x2c],
+    256,
+    256,
+    WSocketError::PayloadTooLarge
+  ),
+  test_read_frame_64bit_len_too_large: (
+    [0x82, 0x7f, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x11, 0x70],
+    256,
+    65536,
+    WSocketError::PayloadTooLarge
+  ),
+  test_read_frame_64bit_len_most_significant_bit_set: (
+    [0x82, 0x7f, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x05],
+    256,
+    usize::MAX,
+    WSocketError::PayloadLengthMostSignificantBitMustBeNull
+  ),
+}
+
+#[cfg(target_pointer_width = "32")]
+#[tokio::test]
+async fn test_read_frame_64bit_len_exceeding_usize() {
+  let input = [0x82, 0x7f, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x05];
+
+  assert!(matches!(
+    recv_message(&input, 256, usize::MAX).await,
+    Err(WSocketError::PayloadTooLarge)
+  ));
+}
+
+fn apply_mask_bytewise(buf: &mut [u8], mask: [u8; 4]) {
+  for (idx, byte) in buf.iter_mut().enumerate() {
+    *byte ^= mask[idx & 3];
+  }
+}
+
+#[test]
+fn test_apply_mask_matches_bytewise() {
+  let mask = [0x37, 0xfa, 0x21, 0x3d];
+  let data = (0..=255u8).cycle().take(300).collect::<Vec<_>>();
+
+  // cover every remainder and unaligned start offsets
+  for start in 0..4 {
+    for len in 0..(data.len() - start) {
+      let mut expected = data[start..start + len].to_vec();
+      apply_mask_bytewise(&mut expected, mask);
+
+      let mut actual = data[start..start + len].to_vec();
+      apply_mask(&mut actual, mask);
+      assert_eq!(actual, expected);
+
+      let mut actual = data[start..start + len].to_vec();
+      apply_mask_word(&mut actual, mask);
+      assert_eq!(actual, expected);
+    }
+  }
+}
+
+/// Records every call to `poll_write` and `poll_write_vectored`.
+struct RecordingWriter {
+  vectored: bool,
+  writes: Vec<Vec<u8>>,
+}
+
+impl RecordingWriter {
+  fn new(vectored: bool) -> Self {
+    Self {
+      vectored,
+      writes: Vec::new(),
+    }
+  }
+}
+
+impl AsyncWrite for RecordingWriter {
+  fn poll_write(
+    mut self: Pin<&mut Self>,
+    _cx: &mut Context<'_>,
+    buf: &[u8],
+  ) -> Poll<std::io::Result<usize>> {
+    self.writes.push(buf.to_vec());
+    Poll::Ready(Ok(buf.len()))
+  }
+
+  fn poll_write_vectored(
+    mut self: Pin<&mut Self>,
+    _cx: &mut Context<'_>,
+    bufs: &[IoSlice<'_>],
+  ) -> Poll<std::io::Result<usize>> {
+    let buf = bufs
+      .iter()
+      .flat_map(|buf| buf.iter().copied())
+      .collect::<Vec<_>>();
+    let len = buf.len();
+    self.writes.push(buf);
+    Poll::Ready(Ok(len))
+  }
+
+  fn is_write_vectored(&self) -> bool {
+    self.vectored
+  }
+
+  fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+    Poll::Ready(Ok(()))
+  }
+
+  fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+    Poll::Ready(Ok(()))
+  }
+}
+
+/// Writes `frame` behind the output pending in `protocol`.
+async fn write_frame(
+  write: &mut RecordingWriter,
+  protocol: &mut Protocol,
+  frame: Frame<'_>,
+) -> WSocketResult<()> {
+  let mut frame = FrameWrite::new(&frame);
+  poll_fn(|cx| frame.poll_write(cx, write, protocol)).await
+}
+
+#[tokio::test]
+async fn test_write_unmasked_frame_vectored() -> WSocketResult<()> {
+  let mut write = RecordingWriter::new(true);
+  let frame = Frame::new(true, OpCode::Text, "Hello".as_bytes());
+  write_frame(&mut write, &mut Protocol::server(1024), frame).await?;
+
+  assert_eq!(write.writes, [[0x81, 0x05, 0x48, 0x65, 0x6c, 0x6c, 0x6f]]);
+
+  Ok(())
+}
+
+#[tokio::test]
+async fn test_write_unmasked_frame_coalesced() -> WSocketResult<()> {
+  let mut write = RecordingWriter::new(false);
+  let frame = Frame::new(true, OpCode::Text, "Hello".as_bytes());
+  write_frame(&mut write, &mut Protocol::server(1024), frame).await?;
+
+  assert_eq!(write.writes, [[0x81, 0x05, 0x48, 0x65, 0x6c, 0x6c, 0x6f]]);
+
+  Ok(())
+}
+
+#[tokio::test]
+async fn test_write_65kib_binary_unmasked_frame() -> WSocketResult<()> {
+  let mut write = RecordingWriter::new(true);
+  let frame = Frame::new(
+    true,
+    OpCode::Binary,
+    include_bytes!("../test/frame_65536_out.bin"),
+  );
+  write_frame(&mut write, &mut Protocol::server(1024), frame).await?;
+
+  assert_eq!(write.writes, [include_bytes!("../test/frame_65536_in.bin")]);
+
+  Ok(())
+}
+
+#[test]
+fn test_encode_masked_frame() {
+  let mut buf = Vec::new();
+  let frame = Frame::new(true, OpCode::Text, "Hello".as_bytes());
+  frame.encode(&mut buf, Some([0x37, 0xfa, 0x21, 0x3d]));
+
+  assert_eq!(
+    buf,
+    [0x81, 0x85, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d, 0x51, 0x58]
+  );
+}
+
+#[test]
+fn test_header_encode_parse_roundtrip() -> WSocketResult<()> {
+  for len in [0, 125, 126, 65535, 65536] {
+    let header = FrameHeader {
+      fin: true,
+      rsv: 0,
+      opcode: OpCode::Binary,
+      mask: Some([0x37, 0xfa, 0x21, 0x3d]),
+      len,
+    };
+
+    let (buf, header_len) = header.encode();
+    assert_eq!(FrameHeader::encoded_len(buf[1]), header_len);
+    assert_eq!(
+      FrameHeader::parse(&buf[..header_len], 65536, Some(true), 0)?,
+      Some(header)
+    );
+  }
+
+  Ok(())
+}
+
+#[test]
+fn test_header_parse_incomplete() -> WSocketResult<()> {
+  let masked = [0x82, 0xfe, 0x01, 0x00, 0x37, 0xfa, 0x21, 0x3d];
+  let unmasked = [0x82, 0x7e, 0x01, 0x00];
+  let masked_long = [
+    0x82, 0xff, 0, 0, 0, 0, 0, 0x01, 0x00, 0x00, 0x37, 0xfa, 0x21, 0x3d,
+  ];
+
+  assert_eq!(FrameHeader::parse(&[], 1024, None, 0)?, None);
+  assert_eq!(FrameHeader::parse(&unmasked[..1], 1024, None, 0)?, None);
+  assert_eq!(FrameHeader::parse(&unmasked[..3], 1024, None, 0)?, None);
+  assert_eq!(FrameHeader::parse(&masked[..7], 1024, None, 0)?, None);
+  assert_eq!(
+    FrameHeader::parse(&masked_long[..9], 1 << 20, None, 0)?,
+    None
+  );
+  assert_eq!(
+    FrameHeader::parse(&masked_long[..13], 1 << 20, None, 0)?,
+    None
+  );
+
+  assert_eq!(
+    FrameHeader::parse(&unmasked, 1024, None, 0)?.map(|header| header.len),
+    Some(256)
+  );
+  assert_eq!(
+    FrameHeader::parse(&masked, 1024, None, 0)?.and_then(|header| header.mask),
+    Some([0x37, 0xfa, 0x21, 0x3d])
+  );
+  assert_eq!(
+    FrameHeader::parse(&masked_long, 1 << 20, None, 0)?.map(|header| header.len),
+    Some(65536)
+  );
+
+  Ok(())
+}
+
+#[test]
+fn test_frame_to_message() {
+  assert_eq!(
+    Message::try_from(Frame::new(true, OpCode::Ping, b"Hello")).unwrap(),
+    Message::Ping(b"Hello")
+  );
+  assert!(matches!(
+    Message::try_from(Frame::new(false, OpCode::Binary, b"Hello")),
+    Err(WSocketError::FramedMessagesAreNotSupported)
+  ));
+  assert!(matches!(
+    Message::try_from(Frame::new(true, OpCode::Close, &[0x03, 0xe8])),
+    Err(WSocketError::ConnectionClosed(close)) if close == Close::new(CloseCode::Normal, None)
+  ));
+}
+
+#[tokio::test]
+async fn test_write_unmasked_frame_behind_pending_bytes() -> WSocketResult<()> {
+  let mut write = RecordingWriter::new(true);
+  let mut protocol = Protocol::server(1024);
+  protocol.send(Message::Ping(&[]))?;
+  let frame = Frame::new(true, OpCode::Text, "Hello".as_bytes());
+  write_frame(&mut write, &mut protocol, frame).await?;
+
+  assert_eq!(
+    write.writes,
+    [[0x89, 0x00, 0x81, 0x05, 0x48, 0x65, 0x6c, 0x6c, 0x6f]]
+  );
+  assert!(protocol.output().is_empty());
+
+  Ok(())
+}
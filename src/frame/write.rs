@@ -0,0 +1,116 @@
+use std::io::{self, IoSlice};
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+
+use tokio::io::AsyncWrite;
+
+use crate::frame::{Frame, MAX_HEADER_LEN};
+use crate::{Protocol, WSocketResult};
+
+/// Payloads up to this length are copied behind their header, if the writer does not support
+/// vectored writes.
+const MAX_COALESCE_LEN: usize = 16 * 1024;
+
+/// Writes an unmasked frame behind the output pending in a [`Protocol`], without copying its
+/// payload into the output buffer.
+///
+/// Progress is kept across polls, so the write can be continued after returning
+/// [`Poll::Pending`]. If it is abandoned halfway, [`buffer_rest`](Self::buffer_rest) moves the
+/// unwritten rest of the frame into the output buffer, so the peer never receives a torn frame.
+pub(crate) struct FrameWrite<'a> {
+  header: [u8; MAX_HEADER_LEN],
+  header_len: usize,
+  data: &'a [u8],
+  /// Amount of header and payload bytes written or moved into the output buffer.
+  written: usize,
+}
+
+impl<'a> FrameWrite<'a> {
+  pub(crate) fn new(frame: &Frame<'a>) -> Self {
+    let (header, header_len) = frame.header(None).encode();
+
+    Self {
+      header,
+      header_len,
+      data: frame.data,
+      written: 0,
+    }
+  }
+
+  /// Whether parts of the frame have been written, so the rest has to follow.
+  pub(crate) fn is_started(&self) -> bool {
+    self.written > 0
+  }
+
+  pub(crate) fn is_done(&self) -> bool {
+    self.written == self.header_len + self.data.len()
+  }
+
+  /// Appends the unwritten rest of the frame to the output buffer of `protocol`.
+  pub(crate) fn buffer_rest(&mut self, protocol: &mut Protocol) {
+    let (header, data) = self.rest();
+    protocol.extend_output(header);
+    protocol.extend_output(data);
+    self.written = self.header_len + self.data.len();
+  }
+
+  /// Writes the output pending in `protocol` followed by the rest of this frame.
+  ///
+  /// Everything is written with vectored writes if supported by `write`. Otherwise small payloads
+  /// are appended to the output buffer, so they are written together with the header.
+  pub(crate) fn poll_write<W: Unpin + AsyncWrite>(
+    &mut self,
+    cx: &mut Context<'_>,
+    write: &mut W,
+    protocol: &mut Protocol,
+  ) -> Poll<WSocketResult<()>> {
+    let vectored = write.is_write_vectored();
+
+    if !vectored && !self.is_started() && self.data.len() <= MAX_COALESCE_LEN {
+      self.buffer_rest(protocol);
+    }
+
+    loop {
+      let pending = protocol.output();
+      let (header, data) = self.rest();
+
+      let written = if vectored {
+        let bufs = [
+          IoSlice::new(pending),
+          IoSlice::new(header),
+          IoSlice::new(data),
+        ];
+
+        if bufs.iter().all(|buf| buf.is_empty()) {
+          return Poll::Ready(Ok(()));
+        }
+
+        ready!(Pin::new(&mut *write).poll_write_vectored(cx, &bufs))?
+      } else {
+        let Some(buf) = [pending, header, data]
+          .into_iter()
+          .find(|buf| !buf.is_empty())
+        else {
+          return Poll::Ready(Ok(()));
+        };
+
+        ready!(Pin::new(&mut *write).poll_write(cx, buf))?
+      };
+
+      if written == 0 {
+        return Poll::Ready(Err(io::Error::from(io::ErrorKind::WriteZero).into()));
+      }
+
+      let from_pending = written.min(pending.len());
+      protocol.consume_output(from_pending);
+      self.written += written - from_pending;
+    }
+  }
+
+  /// The unwritten rest of header and payload.
+  fn rest(&self) -> (&[u8], &'a [u8]) {
+    let header = &self.header[self.written.min(self.header_len)..self.header_len];
+    let data = &self.data[self.written.saturating_sub(self.header_len)..];
+    (header, data)
+  }
+}
@@ -4,7 +4,8 @@ use http_body_util::Empty;
 use hyper::body::{Bytes, Incoming};
 use hyper::client::conn::http1;
 use hyper::header::{
-  CONNECTION, HOST, SEC_WEBSOCKET_KEY, SEC_WEBSOCKET_VERSION, UPGRADE, USER_AGENT,
+  CONNECTION, HOST, SEC_WEBSOCKET_KEY, SEC_WEBSOCKET_PROTOCOL, SEC_WEBSOCKET_VERSION, UPGRADE,
+  USER_AGENT,
 };
 use hyper::upgrade::Upgraded;
 use hyper::StatusCode;
@@ -12,9 +13,10 @@ use hyper::{upgrade, Response};
 use hyper::{Request, Uri};
 use hyper_util::rt::tokio::TokioIo;
 use tokio::io::{AsyncRead, AsyncWrite};
-use tracing::error;
+use tracing::{debug, error, field};
 
-use crate::{WSocketError, WebSocket};
+use crate::ws::connection_span;
+use crate::{Role, WSocketError, WebSocket, WebSocketConfig};
 
 pub async fn handshake<S>(
   socket: S,
@@ -22,32 +24,47 @@ pub async fn handshake<S>(
   host: &str,
   port: u16,
   user_agent: &str,
-  max_payload_len: usize,
-  masking: bool,
+  config: WebSocketConfig,
 ) -> Result<(WebSocket<TokioIo<Upgraded>>, Response<Incoming>), WSocketError>
 where
   S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
 {
+  let span = connection_span(Role::Client);
+  span.record("peer", field::display(format_args!("{}:{}", host, port)));
+
   let req = generate_request(uri, host, port, user_agent);
 
   let io = TokioIo::new(socket);
 
   let (mut sender, conn) = http1::handshake(io).await?;
-  tokio::spawn(async move {
-    if let Err(e) = conn.with_upgrades().await {
-      error!("Error polling connection: {}", e);
+  tokio::spawn({
+    let span = span.clone();
+    async move {
+      if let Err(e) = conn.with_upgrades().await {
+        error!(parent: &span, "Error polling connection: {}", e);
+      }
     }
   });
 
+  debug!(parent: &span, %uri, "sending handshake request");
   let mut response = sender.send_request(req).await?;
-  verify(&response)?;
+  debug!(parent: &span, status = %response.status(), "received handshake response");
+  verify(&response).inspect_err(|err| debug!(parent: &span, %err, "handshake rejected"))?;
 
   let upgraded = upgrade::on(&mut response).await?;
+  debug!(parent: &span, "upgraded to websocket");
+
+  let mut ws = WebSocket::client(TokioIo::new(upgraded), config).with_span(span);
+
+  if let Some(subprotocol) = response
+    .headers()
+    .get(SEC_WEBSOCKET_PROTOCOL)
+    .and_then(|value| value.to_str().ok())
+  {
+    ws = ws.with_subprotocol(subprotocol);
+  }
 
-  Ok((
-    WebSocket::client(TokioIo::new(upgraded), max_payload_len, masking),
-    response,
-  ))
+  Ok((ws, response))
 }
 
 fn generate_request(uri: &Uri, host: &str, port: u16, user_agent: &str) -> Request<Empty<Bytes>> {
@@ -1,15 +1,57 @@
-pub use close::{Close, CloseCode};
+#![cfg_attr(not(feature = "std"), no_std)]
+
+#[cfg(feature = "alloc")]
+extern crate alloc;
+
+#[cfg(feature = "alloc")]
+use alloc::string::{String, ToString};
+#[cfg(feature = "alloc")]
+use alloc::vec::Vec;
+
+#[cfg(feature = "alloc")]
+pub use close::Close;
+pub use close::CloseCode;
+#[cfg(feature = "codec")]
+pub use codec::WebSocketCodec;
+#[cfg(feature = "futures-io")]
+pub use compat::FuturesIo;
+#[cfg(feature = "std")]
+pub use config::WebSocketConfig;
 pub use error::WSocketError;
 pub use error::WSocketResult;
+#[cfg(feature = "alloc")]
+pub use frame::OwnedFrame;
+pub use frame::{apply_mask, Frame, FrameHeader, OpCode, MAX_HEADER_LEN};
 #[cfg(all(feature = "handshake", feature = "client"))]
 pub use handshake::handshake;
+#[cfg(feature = "alloc")]
+pub use protocol::{Limits, Masking, Protocol, Role};
 #[cfg(feature = "upgrade")]
 pub use upgrade::{is_upgrade_request, upgrade};
-pub use ws::WebSocket;
+#[cfg(feature = "tokio")]
+pub use ws::{
+  Closed, ErrorStats, Event, Initiator, IntoSplit, MessageStats, ReuniteError, Stats, TrafficStats,
+  WebSocket,
+};
+#[cfg(feature = "sender")]
+pub use ws::{OverflowPolicy, WebSocketSender};
 
+#[cfg(any(feature = "upgrade", all(feature = "blocking", feature = "client")))]
+mod accept;
+#[cfg(feature = "blocking")]
+pub mod blocking;
 mod close;
+#[cfg(feature = "codec")]
+mod codec;
+#[cfg(feature = "futures-io")]
+mod compat;
+#[cfg(feature = "std")]
+mod config;
 mod error;
 mod frame;
+#[cfg(feature = "alloc")]
+mod protocol;
+#[cfg(feature = "tokio")]
 mod ws;
 
 #[cfg(all(feature = "handshake", feature = "client"))]
@@ -18,9 +60,69 @@ mod handshake;
 #[cfg(feature = "upgrade")]
 mod upgrade;
 
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub enum Message<'a> {
   Binary(&'a [u8]),
   Text(&'a str),
   Ping(&'a [u8]),
   Pong(&'a [u8]),
 }
+
+#[cfg(any(feature = "tokio", feature = "blocking"))]
+impl Message<'_> {
+  /// Copies the payload into `buf`, failing with [`WSocketError::BufferTooSmall`] if it doesn't fit.
+  pub(crate) fn copy_into(self, buf: &mut [u8]) -> WSocketResult<Message<'_>> {
+    let payload = match self {
+      Message::Binary(data) | Message::Ping(data) | Message::Pong(data) => data,
+      Message::Text(text) => text.as_bytes(),
+    };
+
+    let data = buf
+      .get_mut(..payload.len())
+      .ok_or(WSocketError::BufferTooSmall(payload.len()))?;
+    data.copy_from_slice(payload);
+
+    Ok(match self {
+      Message::Binary(_) => Message::Binary(data),
+      // the payload has already been validated as utf8
+      Message::Text(_) => Message::Text(core::str::from_utf8(data).unwrap()),
+      Message::Ping(_) => Message::Ping(data),
+      Message::Pong(_) => Message::Pong(data),
+    })
+  }
+}
+
+/// Same as [`Message`], but owns its payload, so it can be kept around while receiving the next
+/// message or moved to another task.
+#[cfg(feature = "alloc")]
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum OwnedMessage {
+  Binary(Vec<u8>),
+  Text(String),
+  Ping(Vec<u8>),
+  Pong(Vec<u8>),
+}
+
+#[cfg(feature = "alloc")]
+impl OwnedMessage {
+  pub fn as_message(&self) -> Message<'_> {
+    match self {
+      Self::Binary(data) => Message::Binary(data),
+      Self::Text(text) => Message::Text(text),
+      Self::Ping(data) => Message::Ping(data),
+      Self::Pong(data) => Message::Pong(data),
+    }
+  }
+}
+
+#[cfg(feature = "alloc")]
+impl From<Message<'_>> for OwnedMessage {
+  fn from(message: Message<'_>) -> Self {
+    match message {
+      Message::Binary(data) => Self::Binary(data.to_vec()),
+      Message::Text(text) => Self::Text(text.to_string()),
+      Message::Ping(data) => Self::Ping(data.to_vec()),
+      Message::Pong(data) => Self::Pong(data.to_vec()),
+    }
+  }
+}
@@ -0,0 +1,696 @@
+use alloc::vec::Vec;
+use core::ops::Range;
+
+use crate::frame::{apply_mask, Frame, FrameHeader, OpCode};
+use crate::{Close, Message, WSocketError, WSocketResult};
+
+#[cfg(test)]
+mod test;
+
+/// Minimum amount of free space offered by [`Protocol::input_buf`], by default.
+pub(crate) const DEFAULT_READ_BUFFER_LEN: usize = 4096;
+
+const DEFAULT_MAX_FRAME_LEN: usize = 16 << 20;
+const DEFAULT_MAX_MESSAGE_LEN: usize = 64 << 20;
+
+/// The side of the connection a [`WebSocket`](crate::WebSocket) represents.
+/// <https://datatracker.ietf.org/doc/html/rfc6455#section-5.1>
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(
+  feature = "serde",
+  derive(serde::Serialize, serde::Deserialize),
+  serde(rename_all = "snake_case")
+)]
+pub enum Role {
+  /// Masks all frames it sends and expects unmasked frames from the server.
+  #[cfg(feature = "client")]
+  Client,
+  /// Sends unmasked frames and expects masked frames from the client.
+  Server,
+}
+
+/// How strictly the mask bit of received frames is validated.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(
+  feature = "serde",
+  derive(serde::Serialize, serde::Deserialize),
+  serde(rename_all = "snake_case")
+)]
+pub enum Masking {
+  /// Frames with a mask bit not matching the [`Role`] of the peer are rejected with
+  /// [`CloseCode::ProtocolError`](crate::CloseCode::ProtocolError), as required by RFC 6455.
+  Strict,
+  /// Frames are accepted regardless of their mask bit, for peers not following RFC 6455.
+  Lenient,
+}
+
+impl Role {
+  /// Masking key for a frame sent in this role, as only clients mask their frames.
+  pub(crate) fn mask(self) -> Option<[u8; 4]> {
+    match self {
+      #[cfg(feature = "client")]
+      Self::Client => Some(rand::random()),
+      Self::Server => None,
+    }
+  }
+}
+
+impl Masking {
+  /// Whether frames received in `role` have to be masked, [`None`] if they are accepted either
+  /// way.
+  pub(crate) fn expect_masked(self, role: Role) -> Option<bool> {
+    match self {
+      Self::Strict => Some(role == Role::Server),
+      Self::Lenient => None,
+    }
+  }
+}
+
+/// Size limits for one direction of a connection, set using [`Protocol::with_read_limits`] and
+/// [`Protocol::with_write_limits`].
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(
+  feature = "serde",
+  derive(serde::Serialize, serde::Deserialize),
+  serde(default)
+)]
+pub struct Limits {
+  /// Maximum payload length of a single frame. Sent messages exceeding it are split into
+  /// fragments.
+  pub max_frame_len: usize,
+  /// Maximum payload length of a message, after reassembling its fragments.
+  pub max_message_len: usize,
+  /// Maximum amount of frames a single message may be fragmented into.
+  pub max_fragments: usize,
+}
+
+impl Limits {
+  /// Limits frames and messages to `max_payload_len`, without limiting the amount of fragments.
+  pub const fn new(max_payload_len: usize) -> Self {
+    Self {
+      max_frame_len: max_payload_len,
+      max_message_len: max_payload_len,
+      max_fragments: usize::MAX,
+    }
+  }
+}
+
+/// Frames of up to 16 MiB, reassembled to messages of up to 64 MiB.
+impl Default for Limits {
+  fn default() -> Self {
+    Self {
+      max_frame_len: DEFAULT_MAX_FRAME_LEN,
+      max_message_len: DEFAULT_MAX_MESSAGE_LEN,
+      max_fragments: usize::MAX,
+    }
+  }
+}
+
+/// The WebSocket protocol without any io, that [`WebSocket`](crate::WebSocket) is built on.
+///
+/// Received bytes are fed in using [`receive`](Self::receive) or by reading directly into
+/// [`input_buf`](Self::input_buf), decoded messages are taken out by [`recv`](Self::recv).
+/// Sent messages are encoded into an output buffer, that has to be written to the peer using
+/// [`output`](Self::output) and [`consume_output`](Self::consume_output).
+pub struct Protocol {
+  role: Role,
+  masking: Masking,
+  read_limits: Limits,
+  write_limits: Limits,
+  allowed_rsv: u8,
+  read_buffer_len: usize,
+  read_buf: Vec<u8>,
+  read_pos: usize,
+  read_filled: usize,
+  read_needed: usize,
+  write_buf: Vec<u8>,
+  write_pos: usize,
+  payload: Option<PendingPayload>,
+  /// Payload of the fragmented message currently being received.
+  message_buf: Vec<u8>,
+  fragmented: Option<Fragmented>,
+  close_sent: bool,
+}
+
+/// A fragmented message, whose final frame hasn't been received yet.
+#[derive(Copy, Clone)]
+struct Fragmented {
+  opcode: OpCode,
+  fragments: usize,
+}
+
+/// Location of a message decoded by [`Protocol::decode_message`].
+pub(crate) enum Received {
+  /// A message consisting of a single frame, with its payload in the read buffer.
+  Frame(FrameHeader, Range<usize>),
+  /// A message reassembled from fragments, with its payload in the message buffer.
+  Reassembled {
+    opcode: OpCode,
+    #[cfg(feature = "tokio")]
+    fragments: usize,
+  },
+}
+
+/// Payload of a frame, whose header has been taken out using [`Protocol::recv_header`].
+struct PendingPayload {
+  remaining: usize,
+  /// Masking key, rotated to match the next payload byte.
+  mask: Option<[u8; 4]>,
+}
+
+impl Protocol {
+  #[inline]
+  pub fn server(max_payload_len: usize) -> Self {
+    Self::new(Role::Server, Limits::new(max_payload_len))
+  }
+
+  #[inline]
+  #[cfg(feature = "client")]
+  pub fn client(max_payload_len: usize) -> Self {
+    Self::new(Role::Client, Limits::new(max_payload_len))
+  }
+
+  /// Creates a protocol using `limits` for both directions.
+  pub(crate) fn new(role: Role, limits: Limits) -> Self {
+    Self {
+      role,
+      masking: Masking::Strict,
+      read_limits: limits,
+      write_limits: limits,
+      allowed_rsv: 0,
+      read_buffer_len: DEFAULT_READ_BUFFER_LEN,
+      read_buf: Vec::new(),
+      read_pos: 0,
+      read_filled: 0,
+      read_needed: 0,
+      write_buf: Vec::new(),
+      write_pos: 0,
+      payload: None,
+      message_buf: Vec::new(),
+      fragmented: None,
+      close_sent: false,
+    }
+  }
+
+  #[inline]
+  pub fn with_masking(mut self, masking: Masking) -> Self {
+    self.masking = masking;
+    self
+  }
+
+  /// Reserved bits, that may be set in received frames, e.g. `0b100` to allow `RSV1` if an
+  /// extension using it has been negotiated. All other reserved bits have to be `0`.
+  #[inline]
+  pub fn with_allowed_rsv(mut self, allowed_rsv: u8) -> Self {
+    self.allowed_rsv = allowed_rsv & 0b111;
+    self
+  }
+
+  /// Limits for received frames and messages. Both default to the `max_payload_len` given on
+  /// construction.
+  #[inline]
+  pub fn with_read_limits(mut self, limits: Limits) -> Self {
+    self.read_limits = limits;
+    self
+  }
+
+  /// Limits for sent frames and messages. Messages longer than
+  /// [`max_frame_len`](Limits::max_frame_len) are split into fragments.
+  #[inline]
+  pub fn with_write_limits(mut self, limits: Limits) -> Self {
+    self.write_limits = limits;
+    self
+  }
+
+  /// Minimum amount of free space offered by [`input_buf`](Self::input_buf), i.e. how much is
+  /// read at once. The buffer grows beyond it to fit larger frames.
+  #[inline]
+  pub fn with_read_buffer_len(mut self, read_buffer_len: usize) -> Self {
+    self.read_buffer_len = read_buffer_len.max(1);
+    self
+  }
+
+  pub fn role(&self) -> Role {
+    self.role
+  }
+
+  pub fn read_limits(&self) -> Limits {
+    self.read_limits
+  }
+
+  pub fn write_limits(&self) -> Limits {
+    self.write_limits
+  }
+
+  /// Copies received bytes into the input buffer.
+  pub fn receive(&mut self, data: &[u8]) {
+    let mut data = data;
+
+    while !data.is_empty() {
+      let buf = self.input_buf();
+      let len = buf.len().min(data.len());
+      buf[..len].copy_from_slice(&data[..len]);
+      self.commit_input(len);
+      data = &data[len..];
+    }
+  }
+
+  /// Free space to read received bytes into, which have to be committed using
+  /// [`commit_input`](Self::commit_input). It grows towards the length of the frame currently
+  /// being received as its bytes arrive, so a frame announcing a large payload doesn't allocate it
+  /// up front.
+  pub fn input_buf(&mut self) -> &mut [u8] {
+    if self.read_pos > 0 {
+      self
+        .read_buf
+        .copy_within(self.read_pos..self.read_filled, 0);
+      self.read_filled -= self.read_pos;
+      self.read_pos = 0;
+    }
+
+    let len = self
+      .read_needed
+      .min(self.read_filled.saturating_mul(2))
+      .max(self.read_filled + self.read_buffer_len);
+
+    if self.read_buf.len() < len {
+      self.read_buf.resize(len, 0);
+    }
+
+    &mut self.read_buf[self.read_filled..]
+  }
+
+  /// Marks `len` bytes of [`input_buf`](Self::input_buf) as received.
+  pub fn commit_input(&mut self, len: usize) {
+    assert!(self.read_filled + len <= self.read_buf.len());
+    self.read_filled += len;
+  }
+
+  /// Decodes the next message from the received bytes, [`None`] if more bytes are required.
+  ///
+  /// Fragmented messages are reassembled, control frames received in between are returned right
+  /// away. A received close frame is returned as [`WSocketError::ConnectionClosed`].
+  pub fn recv(&mut self) -> WSocketResult<Option<Message<'_>>> {
+    match self.decode_message()? {
+      Some(received) => self.message(received).map(Some),
+      None => Ok(None),
+    }
+  }
+
+  /// Decodes frames until a message is complete, reassembling fragmented messages.
+  pub(crate) fn decode_message(&mut self) -> WSocketResult<Option<Received>> {
+    while let Some((header, payload)) = self.decode()? {
+      if header.opcode.is_control() {
+        return Ok(Some(Received::Frame(header, payload)));
+      }
+
+      let opcode = match (self.fragmented, header.opcode) {
+        (None, OpCode::Continuation) => return Err(WSocketError::UnexpectedContinuationFrame),
+        (None, _) if header.fin => {
+          check_message_len(&self.read_limits, header.len)?;
+          return Ok(Some(Received::Frame(header, payload)));
+        }
+        (None, opcode) => {
+          self.message_buf.clear();
+          opcode
+        }
+        (Some(fragmented), OpCode::Continuation) => fragmented.opcode,
+        (Some(_), _) => return Err(WSocketError::ExpectedContinuationFrame),
+      };
+
+      let fragments = self.fragmented.map_or(0, |fragmented| fragmented.fragments) + 1;
+
+      if fragments > self.read_limits.max_fragments {
+        return Err(WSocketError::TooManyFragments);
+      }
+
+      check_message_len(&self.read_limits, self.message_buf.len() + header.len)?;
+      self.message_buf.extend_from_slice(&self.read_buf[payload]);
+
+      if header.fin {
+        self.fragmented = None;
+        return Ok(Some(Received::Reassembled {
+          opcode,
+          #[cfg(feature = "tokio")]
+          fragments,
+        }));
+      }
+
+      self.fragmented = Some(Fragmented { opcode, fragments });
+    }
+
+    Ok(None)
+  }
+
+  /// Decodes the next frame without interpreting it, [`None`] if more bytes are required.
+  ///
+  /// The payload is already unmasked, the masking key is still part of the header.
+  pub fn recv_frame(&mut self) -> WSocketResult<Option<(FrameHeader, &[u8])>> {
+    match self.decode()? {
+      Some((header, range)) => Ok(Some((header, self.payload(range)))),
+      None => Ok(None),
+    }
+  }
+
+  /// Decodes the next frame, returning its header and the location of its unmasked payload.
+  pub(crate) fn decode(&mut self) -> WSocketResult<Option<(FrameHeader, Range<usize>)>> {
+    let Some((header, header_len)) = self.peek_header()? else {
+      return Ok(None);
+    };
+
+    let available = self.read_filled - self.read_pos;
+    let frame_len = header_len
+      .checked_add(header.len)
+      .ok_or(WSocketError::PayloadTooLarge)?;
+
+    if available < frame_len {
+      self.read_needed = frame_len;
+      return Ok(None);
+    }
+
+    let payload = self.read_pos + header_len..self.read_pos + frame_len;
+
+    if let Some(mask) = header.mask {
+      apply_mask(&mut self.read_buf[payload.clone()], mask);
+    }
+
+    self.read_pos = payload.end;
+    self.read_needed = 0;
+
+    Ok(Some((header, payload)))
+  }
+
+  /// Decodes only the header of the next frame, [`None`] if more bytes are required.
+  ///
+  /// Its payload is taken out using [`read_payload`](Self::read_payload) or dropped using
+  /// [`skip_payload`](Self::skip_payload). Payload left over is skipped, once the next frame is
+  /// decoded.
+  pub fn recv_header(&mut self) -> WSocketResult<Option<FrameHeader>> {
+    let Some((header, header_len)) = self.peek_header()? else {
+      return Ok(None);
+    };
+
+    self.read_pos += header_len;
+    self.read_needed = 0;
+
+    if header.len > 0 {
+      self.payload = Some(PendingPayload {
+        remaining: header.len,
+        mask: header.mask,
+      });
+    }
+
+    Ok(Some(header))
+  }
+
+  /// Amount of payload bytes of the frame received by [`recv_header`](Self::recv_header), that
+  /// haven't been read or skipped yet.
+  pub fn payload_remaining(&self) -> usize {
+    self.payload.as_ref().map_or(0, |payload| payload.remaining)
+  }
+
+  /// Whether parts of a frame have been received, that hasn't been decoded completely yet.
+  #[cfg(feature = "tokio")]
+  pub(crate) fn is_receiving_frame(&self) -> bool {
+    self.read_filled > self.read_pos || self.payload.is_some()
+  }
+
+  /// Copies the received part of the payload following [`recv_header`](Self::recv_header) into
+  /// `buf` and unmasks it, returning the amount of bytes copied.
+  pub fn read_payload(&mut self, buf: &mut [u8]) -> usize {
+    let Some(payload) = &self.payload else {
+      return 0;
+    };
+
+    let mask = payload.mask;
+    let len = buf
+      .len()
+      .min(payload.remaining)
+      .min(self.read_filled - self.read_pos);
+
+    buf[..len].copy_from_slice(&self.read_buf[self.read_pos..self.read_pos + len]);
+
+    if let Some(mask) = mask {
+      apply_mask(&mut buf[..len], mask);
+    }
+
+    self.consume_payload(len);
+    len
+  }
+
+  /// Drops the received part of the payload following [`recv_header`](Self::recv_header),
+  /// returning whether the payload has been skipped completely.
+  pub fn skip_payload(&mut self) -> bool {
+    let len = self
+      .payload_remaining()
+      .min(self.read_filled - self.read_pos);
+    self.consume_payload(len);
+
+    self.payload.is_none()
+  }
+
+  fn consume_payload(&mut self, len: usize) {
+    let Some(payload) = &mut self.payload else {
+      return;
+    };
+
+    self.read_pos += len;
+    payload.remaining -= len;
+
+    if let Some(mask) = &mut payload.mask {
+      mask.rotate_left(len % 4);
+    }
+
+    if payload.remaining == 0 {
+      self.payload = None;
+    }
+  }
+
+  /// Parses the header of the next frame, without consuming it. Payload left over from
+  /// [`recv_header`](Self::recv_header) is skipped first.
+  fn peek_header(&mut self) -> WSocketResult<Option<(FrameHeader, usize)>> {
+    if !self.skip_payload() {
+      return Ok(None);
+    }
+
+    let available = &self.read_buf[self.read_pos..self.read_filled];
+
+    if available.len() < 2 {
+      return Ok(None);
+    }
+
+    let header_len = FrameHeader::encoded_len(available[1]);
+
+    let Some(header) = FrameHeader::parse(
+      available,
+      self.read_limits.max_frame_len,
+      self.masking.expect_masked(self.role),
+      self.allowed_rsv,
+    )?
+    else {
+      self.read_needed = header_len;
+      return Ok(None);
+    };
+
+    Ok(Some((header, header_len)))
+  }
+
+  /// Payload of a decoded frame.
+  pub(crate) fn payload(&self, payload: Range<usize>) -> &[u8] {
+    &self.read_buf[payload]
+  }
+
+  /// Turns a decoded message into a [`Message`] borrowing its payload.
+  pub(crate) fn message(&self, received: Received) -> WSocketResult<Message<'_>> {
+    let frame = match received {
+      Received::Frame(header, payload) => {
+        Frame::new(header.fin, header.opcode, self.payload(payload)).with_rsv(header.rsv)
+      }
+      Received::Reassembled { opcode, .. } => Frame::new(true, opcode, &self.message_buf),
+    };
+
+    Message::try_from(frame)
+  }
+
+  /// Payload length of a decoded message.
+  #[cfg(feature = "tokio")]
+  pub(crate) fn message_len(&self, received: &Received) -> usize {
+    match received {
+      Received::Frame(_, payload) => payload.len(),
+      Received::Reassembled { .. } => self.message_buf.len(),
+    }
+  }
+
+  /// Encodes the message into the output buffer, split into fragments if it exceeds the
+  /// [`max_frame_len`](Limits::max_frame_len) of the write limits.
+  pub fn send(&mut self, message: Message<'_>) -> WSocketResult<()> {
+    self.encode_message(Frame::from(message))
+  }
+
+  /// Encodes the frame into the output buffer, masking it if required by the role.
+  pub fn send_frame(&mut self, frame: Frame<'_>) -> WSocketResult<()> {
+    self.encode(frame)
+  }
+
+  /// Encodes a close frame into the output buffer. Does nothing, if a close frame has been
+  /// sent already.
+  pub fn close(&mut self, close: &Close) -> WSocketResult<()> {
+    if self.close_sent {
+      return Ok(());
+    }
+
+    let buf = close.encode()?;
+    self.encode(Frame::new(true, OpCode::Close, &buf))
+  }
+
+  /// Whether a close frame has been encoded into the output buffer.
+  #[inline]
+  pub fn is_close_sent(&self) -> bool {
+    self.close_sent
+  }
+
+  /// Appends the final frame of a message to the output buffer, split into fragments if it
+  /// exceeds the max frame length. Nothing is appended, if the message exceeds any limit.
+  pub(crate) fn encode_message(&mut self, frame: Frame<'_>) -> WSocketResult<()> {
+    let limits = self.write_limits;
+    check_message_len(&limits, frame.data.len())?;
+
+    let frames = self.frame_count(&frame);
+
+    if frames == 1 {
+      return self.encode(frame);
+    }
+
+    if frames > limits.max_fragments {
+      return Err(WSocketError::TooManyFragments);
+    }
+
+    let mut chunks = frame.data.chunks(limits.max_frame_len.max(1)).peekable();
+    let mut fragment = frame;
+
+    while let Some(chunk) = chunks.next() {
+      fragment.fin = chunks.peek().is_none();
+      fragment.data = chunk;
+      self.encode(fragment)?;
+
+      fragment.opcode = OpCode::Continuation;
+      fragment.rsv = 0;
+    }
+
+    Ok(())
+  }
+
+  /// Amount of frames [`encode_message`](Self::encode_message) splits the message into.
+  pub(crate) fn frame_count(&self, frame: &Frame<'_>) -> usize {
+    let max_frame_len = self.write_limits.max_frame_len;
+
+    // control frames must not be fragmented, they are rejected by `encode` if too long
+    if frame.data.len() <= max_frame_len || frame.opcode.is_control() {
+      return 1;
+    }
+
+    frame.data.len().div_ceil(max_frame_len.max(1))
+  }
+
+  /// Appends the frame to the output buffer, masking it if required by the role.
+  pub(crate) fn encode(&mut self, frame: Frame<'_>) -> WSocketResult<()> {
+    frame.check_control()?;
+
+    // close frames are sent regardless of the limits, e.g. to report a message that was too big
+    if frame.opcode != OpCode::Close {
+      self.check_frame_len(frame.data.len())?;
+    }
+
+    frame.encode(&mut self.write_buf, self.role.mask());
+    self.close_sent |= frame.opcode == OpCode::Close;
+
+    Ok(())
+  }
+
+  /// Fails if a sent frame would exceed the max frame length.
+  pub(crate) fn check_frame_len(&self, len: usize) -> WSocketResult<()> {
+    if len > self.write_limits.max_frame_len {
+      return Err(WSocketError::PayloadTooLarge);
+    }
+
+    Ok(())
+  }
+
+  /// Fails if a sent message would exceed the max message length.
+  #[cfg(feature = "tokio")]
+  pub(crate) fn check_message_len(&self, len: usize) -> WSocketResult<()> {
+    check_message_len(&self.write_limits, len)
+  }
+
+  /// Encoded bytes, that haven't been sent to the peer yet.
+  pub fn output(&self) -> &[u8] {
+    &self.write_buf[self.write_pos..]
+  }
+
+  /// Marks `len` bytes of [`output`](Self::output) as sent.
+  pub fn consume_output(&mut self, len: usize) {
+    assert!(self.write_pos + len <= self.write_buf.len());
+    self.write_pos += len;
+
+    if self.write_pos == self.write_buf.len() {
+      self.write_buf.clear();
+      self.write_pos = 0;
+    }
+  }
+
+  /// Appends already encoded bytes to the output buffer.
+  #[cfg(feature = "tokio")]
+  pub(crate) fn extend_output(&mut self, bytes: &[u8]) {
+    self.write_buf.extend_from_slice(bytes);
+  }
+
+  /// Splits into a protocol only used for receiving and one only used for sending.
+  #[cfg(feature = "tokio")]
+  pub(crate) fn split(self) -> (Self, Self) {
+    let write = Self {
+      write_buf: self.write_buf,
+      write_pos: self.write_pos,
+      close_sent: self.close_sent,
+      ..Self::new(self.role, self.read_limits)
+        .with_masking(self.masking)
+        .with_allowed_rsv(self.allowed_rsv)
+        .with_read_buffer_len(self.read_buffer_len)
+        .with_write_limits(self.write_limits)
+    };
+
+    let read = Self {
+      write_buf: Vec::new(),
+      write_pos: 0,
+      ..self
+    };
+
+    (read, write)
+  }
+
+  /// Joins the protocols returned by [`split`](Self::split) again.
+  #[cfg(feature = "tokio")]
+  pub(crate) fn unsplit(read: Self, write: Self) -> Self {
+    Self {
+      write_buf: write.write_buf,
+      write_pos: write.write_pos,
+      close_sent: write.close_sent,
+      ..read
+    }
+  }
+
+  /// Bytes received, that haven't been decoded yet.
+  #[cfg(feature = "tokio")]
+  pub(crate) fn into_input(mut self) -> Vec<u8> {
+    self.read_buf.truncate(self.read_filled);
+    self.read_buf.drain(..self.read_pos);
+    self.read_buf
+  }
+}
+
+fn check_message_len(limits: &Limits, len: usize) -> WSocketResult<()> {
+  if len > limits.max_message_len {
+    return Err(WSocketError::PayloadTooLarge);
+  }
+
+  Ok(())
+}
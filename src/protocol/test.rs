@@ -0,0 +1,333 @@
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::{
+  Close, CloseCode, Frame, FrameHeader, Limits, Masking, Message, OpCode, Protocol, WSocketError,
+  WSocketResult,
+};
+
+#[test]
+fn test_recv_in_pieces() -> WSocketResult<()> {
+  let input = [
+    0x89, 0x85, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d, 0x51, 0x58,
+  ];
+  let mut protocol = Protocol::server(1024);
+
+  for byte in &input[..input.len() - 1] {
+    protocol.receive(&[*byte]);
+    assert_eq!(protocol.recv()?, None);
+  }
+
+  protocol.receive(&input[input.len() - 1..]);
+  assert_eq!(protocol.recv()?, Some(Message::Ping(b"Hello")));
+  assert_eq!(protocol.recv()?, None);
+
+  Ok(())
+}
+
+#[test]
+fn test_recv_multiple_messages_at_once() -> WSocketResult<()> {
+  let mut protocol = Protocol::server(1024).with_masking(Masking::Lenient);
+  protocol.receive(&[0x82, 0x02, 0x48, 0x65, 0x8a, 0x00, 0x82]);
+
+  assert_eq!(protocol.recv()?, Some(Message::Binary(b"He")));
+  assert_eq!(protocol.recv()?, Some(Message::Pong(b"")));
+  assert_eq!(protocol.recv()?, None);
+
+  protocol.receive(&[0x01, 0x6c]);
+  assert_eq!(protocol.recv()?, Some(Message::Binary(b"l")));
+
+  Ok(())
+}
+
+#[test]
+fn test_recv_frame_larger_than_read_chunk() -> WSocketResult<()> {
+  let mut protocol = Protocol::server(65536).with_masking(Masking::Lenient);
+  protocol.receive(include_bytes!("../test/frame_65536_in.bin"));
+
+  assert_eq!(
+    protocol.recv()?,
+    Some(Message::Binary(include_bytes!(
+      "../test/frame_65536_out.bin"
+    )))
+  );
+
+  Ok(())
+}
+
+#[test]
+fn test_input_buf_grows_with_received_bytes() -> WSocketResult<()> {
+  let mut protocol = Protocol::server(16 << 20).with_masking(Masking::Lenient);
+  protocol.receive(&[0x82, 0x7f, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00]);
+
+  assert_eq!(protocol.recv()?, None);
+  assert!(protocol.input_buf().len() < 1 << 20);
+
+  let payload = vec![0; 16 << 20];
+  protocol.receive(&payload);
+  assert_eq!(protocol.recv()?, Some(Message::Binary(&payload)));
+
+  Ok(())
+}
+
+#[test]
+fn test_recv_close() {
+  let mut protocol = Protocol::server(1024).with_masking(Masking::Lenient);
+  protocol.receive(&[0x88, 0x02, 0x03, 0xe8]);
+
+  assert!(matches!(
+    protocol.recv(),
+    Err(WSocketError::ConnectionClosed(close)) if close == Close::new(CloseCode::Normal, None)
+  ));
+}
+
+#[test]
+fn test_send_and_consume_output() -> WSocketResult<()> {
+  let mut protocol = Protocol::server(1024);
+  protocol.send(Message::Binary(b"Hello"))?;
+  protocol.close(&Close::new(CloseCode::Normal, None))?;
+
+  assert_eq!(
+    protocol.output(),
+    [0x82, 0x05, 0x48, 0x65, 0x6c, 0x6c, 0x6f, 0x88, 0x02, 0x03, 0xe8]
+  );
+
+  protocol.consume_output(7);
+  assert_eq!(protocol.output(), [0x88, 0x02, 0x03, 0xe8]);
+
+  protocol.consume_output(4);
+  assert!(protocol.output().is_empty());
+
+  Ok(())
+}
+
+#[test]
+fn test_send_bodyless_close_once() -> WSocketResult<()> {
+  let mut protocol = Protocol::server(1024);
+  protocol.send_frame(Frame::new(true, OpCode::Close, &[]))?;
+  protocol.close(&Close::new(CloseCode::NoStatusRcvd, None))?;
+  protocol.close(&Close::new(CloseCode::Normal, None))?;
+
+  assert!(protocol.is_close_sent());
+  assert_eq!(protocol.output(), [0x88, 0x00]);
+
+  Ok(())
+}
+
+#[test]
+fn test_send_payload_too_large() {
+  let mut protocol = Protocol::server(4);
+
+  assert!(matches!(
+    protocol.send(Message::Binary(b"Hello")),
+    Err(WSocketError::PayloadTooLarge)
+  ));
+  assert!(protocol.output().is_empty());
+}
+
+#[test]
+fn test_frame_with_rsv_roundtrip() -> WSocketResult<()> {
+  let mut sender = Protocol::server(1024);
+  sender.send_frame(Frame::new(false, OpCode::Binary, b"Hel").with_rsv(0b100))?;
+  sender.send_frame(Frame::new(true, OpCode::Continuation, b"lo"))?;
+
+  let mut receiver = Protocol::server(1024)
+    .with_masking(Masking::Lenient)
+    .with_allowed_rsv(0b100);
+  receiver.receive(sender.output());
+
+  let (header, payload) = receiver.recv_frame()?.unwrap();
+  assert_eq!(
+    header,
+    FrameHeader {
+      fin: false,
+      rsv: 0b100,
+      opcode: OpCode::Binary,
+      mask: None,
+      len: 3,
+    }
+  );
+  assert_eq!(payload, b"Hel");
+
+  let (header, payload) = receiver.recv_frame()?.unwrap();
+  assert!(header.fin);
+  assert_eq!(header.opcode, OpCode::Continuation);
+  assert_eq!(payload, b"lo");
+
+  Ok(())
+}
+
+#[test]
+fn test_recv_rejects_disallowed_rsv() {
+  let mut protocol = Protocol::server(1024).with_masking(Masking::Lenient);
+  protocol.receive(&[0xc2, 0x00]);
+
+  assert!(matches!(
+    protocol.recv_frame(),
+    Err(WSocketError::ReserveBitMustBeNull)
+  ));
+}
+
+#[test]
+fn test_recv_header_and_payload_in_pieces() -> WSocketResult<()> {
+  let input = [
+    0x89, 0x85, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d, 0x51, 0x58,
+  ];
+  let mut protocol = Protocol::server(1024);
+
+  protocol.receive(&input[..7]);
+  let header = protocol.recv_header()?.unwrap();
+  assert_eq!((header.opcode, header.len), (OpCode::Ping, 5));
+
+  let mut payload = Vec::new();
+  let mut buf = [0u8; 2];
+
+  for byte in &input[7..] {
+    protocol.receive(&[*byte]);
+    let len = protocol.read_payload(&mut buf);
+    payload.extend_from_slice(&buf[..len]);
+  }
+
+  assert_eq!(payload, b"Hello");
+  assert_eq!(protocol.payload_remaining(), 0);
+
+  Ok(())
+}
+
+#[test]
+fn test_recv_skips_left_over_payload() -> WSocketResult<()> {
+  let mut protocol = Protocol::server(1024).with_masking(Masking::Lenient);
+  protocol.receive(&[0x82, 0x05, 0x48, 0x65]);
+
+  let header = protocol.recv_header()?.unwrap();
+  assert_eq!(header.len, 5);
+  assert!(!protocol.skip_payload());
+  assert_eq!(protocol.payload_remaining(), 3);
+
+  protocol.receive(&[0x6c, 0x6c, 0x6f, 0x8a, 0x02, 0x48, 0x69]);
+  assert_eq!(protocol.recv()?, Some(Message::Pong(b"Hi")));
+
+  Ok(())
+}
+
+#[test]
+fn test_recv_reassembles_fragments_around_control_frames() -> WSocketResult<()> {
+  let mut protocol = Protocol::server(1024).with_masking(Masking::Lenient);
+  protocol.receive(&[0x02, 0x02, 0x48, 0x65, 0x89, 0x00, 0x00, 0x01, 0x6c]);
+
+  assert_eq!(protocol.recv()?, Some(Message::Ping(b"")));
+  assert_eq!(protocol.recv()?, None);
+
+  protocol.receive(&[0x80, 0x02, 0x6c, 0x6f]);
+  assert_eq!(protocol.recv()?, Some(Message::Binary(b"Hello")));
+
+  Ok(())
+}
+
+#[test]
+fn test_recv_limits() {
+  let input = [
+    0x02, 0x02, 0x48, 0x65, 0x00, 0x01, 0x6c, 0x80, 0x02, 0x6c, 0x6f,
+  ];
+  let limits = Limits::new(1024);
+
+  let mut protocol = Protocol::server(1024)
+    .with_masking(Masking::Lenient)
+    .with_read_limits(Limits {
+      max_frame_len: 1,
+      ..limits
+    });
+  protocol.receive(&input);
+  assert!(matches!(
+    protocol.recv(),
+    Err(WSocketError::PayloadTooLarge)
+  ));
+
+  let mut protocol = Protocol::server(1024)
+    .with_masking(Masking::Lenient)
+    .with_read_limits(Limits {
+      max_message_len: 4,
+      ..limits
+    });
+  protocol.receive(&input);
+  assert!(matches!(
+    protocol.recv(),
+    Err(WSocketError::PayloadTooLarge)
+  ));
+
+  let mut protocol = Protocol::server(1024)
+    .with_masking(Masking::Lenient)
+    .with_read_limits(Limits {
+      max_fragments: 2,
+      ..limits
+    });
+  protocol.receive(&input);
+  assert!(matches!(
+    protocol.recv(),
+    Err(WSocketError::TooManyFragments)
+  ));
+}
+
+#[test]
+fn test_recv_rejects_interrupted_fragments() {
+  let mut protocol = Protocol::server(1024).with_masking(Masking::Lenient);
+  protocol.receive(&[0x80, 0x00]);
+  assert!(matches!(
+    protocol.recv(),
+    Err(WSocketError::UnexpectedContinuationFrame)
+  ));
+
+  let mut protocol = Protocol::server(1024).with_masking(Masking::Lenient);
+  protocol.receive(&[0x02, 0x01, 0x48, 0x82, 0x00]);
+  assert!(matches!(
+    protocol.recv(),
+    Err(WSocketError::ExpectedContinuationFrame)
+  ));
+}
+
+#[test]
+fn test_send_splits_into_fragments() -> WSocketResult<()> {
+  let mut protocol = Protocol::server(1024).with_write_limits(Limits {
+    max_frame_len: 2,
+    ..Limits::new(1024)
+  });
+  protocol.send(Message::Binary(b"Hello"))?;
+  protocol.send(Message::Ping(b"Hi"))?;
+
+  assert_eq!(
+    protocol.output(),
+    [0x02, 0x02, 0x48, 0x65, 0x00, 0x02, 0x6c, 0x6c, 0x80, 0x01, 0x6f, 0x89, 0x02, 0x48, 0x69]
+  );
+
+  Ok(())
+}
+
+#[test]
+fn test_send_too_many_fragments() {
+  let mut protocol = Protocol::server(1024).with_write_limits(Limits {
+    max_frame_len: 2,
+    max_message_len: 1024,
+    max_fragments: 2,
+  });
+
+  assert!(matches!(
+    protocol.send(Message::Binary(b"Hello")),
+    Err(WSocketError::TooManyFragments)
+  ));
+  assert!(protocol.output().is_empty());
+}
+
+#[test]
+fn test_send_invalid_control_frames() {
+  let mut protocol = Protocol::server(1024);
+
+  assert!(matches!(
+    protocol.send_frame(Frame::new(false, OpCode::Ping, b"")),
+    Err(WSocketError::ControlFrameMustNotBeFragmented)
+  ));
+  assert!(matches!(
+    protocol.send(Message::Ping(&[0; 126])),
+    Err(WSocketError::ControlFrameMustHaveAPayloadLengthOf125BytesOrLess)
+  ));
+  assert!(protocol.output().is_empty());
+}
@@ -3,8 +3,6 @@ use std::pin::Pin;
 use std::task::Context;
 use std::task::Poll;
 
-use base64::engine::general_purpose::STANDARD;
-use base64::Engine;
 use http_body_util::Full;
 use hyper::body::Bytes;
 use hyper::header::{
@@ -16,23 +14,28 @@ use hyper::Response;
 use hyper::{HeaderMap, Request};
 use hyper_util::rt::TokioIo;
 use pin_project_lite::pin_project;
-use sha1::{Digest, Sha1};
+use tracing::{debug, Span};
 
-use crate::{WSocketError, WebSocket};
+use crate::accept::sec_websocket_accept;
+use crate::ws::connection_span;
+use crate::{Role, WSocketError, WebSocket, WebSocketConfig};
 
 pin_project! {
   pub struct UpgradeFuture {
     #[pin]
     inner: hyper::upgrade::OnUpgrade,
-    max_payload_len: usize,
+    config: Option<WebSocketConfig>,
+    span: Span,
   }
 }
 
 pub fn upgrade<B>(
   mut request: impl std::borrow::BorrowMut<Request<B>>,
-  max_payload_len: usize,
+  config: WebSocketConfig,
 ) -> Result<(Response<Full<Bytes>>, UpgradeFuture), WSocketError> {
   let request = request.borrow_mut();
+  let span = connection_span(Role::Server);
+  debug!(parent: &span, uri = %request.uri(), "received upgrade request");
 
   let key = request
     .headers()
@@ -52,16 +55,15 @@ pub fn upgrade<B>(
     .status(hyper::StatusCode::SWITCHING_PROTOCOLS)
     .header(CONNECTION, "upgrade")
     .header(UPGRADE, "websocket")
-    .header(
-      SEC_WEBSOCKET_ACCEPT,
-      &sec_websocket_protocol(key.as_bytes()),
-    )
+    .header(SEC_WEBSOCKET_ACCEPT, sec_websocket_accept(key.as_bytes()))
     .body(Full::new(Bytes::from("switching to websocket protocol")))
     .expect("bug: failed to build response");
+  debug!(parent: &span, "accepted upgrade request");
 
   let stream = UpgradeFuture {
     inner: hyper::upgrade::on(request),
-    max_payload_len,
+    config: Some(config),
+    span,
   };
 
   Ok((response, stream))
@@ -72,14 +74,6 @@ pub fn is_upgrade_request<B>(request: &Request<B>) -> bool {
     && header_contains_value(request.headers(), UPGRADE, "websocket")
 }
 
-fn sec_websocket_protocol(key: &[u8]) -> String {
-  let mut sha1 = Sha1::default();
-  sha1.update(key);
-  sha1.update(b"258EAFA5-E914-47DA-95CA-C5AB0DC85B11"); // magic string
-  let result = sha1.finalize();
-  STANDARD.encode(&result[..])
-}
-
 fn header_contains_value(headers: &HeaderMap, header: HeaderName, value: impl AsRef<[u8]>) -> bool {
   let value = value.as_ref();
   for header in headers.get_all(header) {
@@ -119,14 +113,24 @@ impl Future for UpgradeFuture {
 
   fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
     let this = self.project();
+    let span = &*this.span;
 
     let upgraded = match this.inner.poll(cx) {
       Poll::Pending => return Poll::Pending,
       Poll::Ready(Ok(upgraded)) => upgraded,
-      Poll::Ready(Err(err)) => return Poll::Ready(Err(err.into())),
+      Poll::Ready(Err(err)) => {
+        debug!(parent: span, %err, "upgrade failed");
+        return Poll::Ready(Err(err.into()));
+      }
     };
 
+    debug!(parent: span, "upgraded to websocket");
+
     let io = TokioIo::new(upgraded);
-    Poll::Ready(Ok(WebSocket::server(io, *this.max_payload_len)))
+    let config = this
+      .config
+      .take()
+      .expect("UpgradeFuture polled after completion");
+    Poll::Ready(Ok(WebSocket::server(io, config).with_span(span.clone())))
   }
 }
@@ -0,0 +1,92 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::task::Waker;
+
+use crate::Close;
+#[cfg(feature = "sender")]
+use std::task::{Context, Poll};
+
+/// Control frames the reading side of a connection wants to send, e.g. pongs answering received
+/// pings or the close frame once receiving failed. They are written while receiving, if the io is
+/// writable, otherwise they are picked up by the writing side with its next write or flush.
+#[derive(Default)]
+pub(crate) struct ControlQueue {
+  pending: AtomicBool,
+  inner: Mutex<Pending>,
+}
+
+#[derive(Default)]
+struct Pending {
+  ping: bool,
+  /// Only the pong answering the most recent ping is sent, as permitted by RFC 6455.
+  pong: Option<Vec<u8>>,
+  /// Close frame to send, replacing all other control frames.
+  close: Option<Close>,
+  /// The writer task waiting for control frames, if any.
+  writer: Option<Waker>,
+}
+
+impl ControlQueue {
+  pub(crate) fn ping(&self) {
+    self.queue(|pending| pending.ping = true);
+  }
+
+  pub(crate) fn pong(&self, payload: &[u8]) {
+    self.queue(|pending| pending.pong = Some(payload.to_vec()));
+  }
+
+  /// Queues the close frame, unless one has been queued already.
+  pub(crate) fn close(&self, close: Close) {
+    self.queue(|pending| {
+      pending.close.get_or_insert(close);
+    });
+  }
+
+  #[inline]
+  pub(crate) fn is_pending(&self) -> bool {
+    self.pending.load(Ordering::SeqCst)
+  }
+
+  fn queue(&self, queue: impl FnOnce(&mut Pending)) {
+    let mut inner = self.inner.lock().unwrap();
+    queue(&mut inner);
+    self.pending.store(true, Ordering::SeqCst);
+
+    if let Some(waker) = inner.writer.take() {
+      waker.wake();
+    }
+  }
+
+  /// Ready once control frames are queued.
+  #[cfg(feature = "sender")]
+  pub(crate) fn poll_pending(&self, cx: &mut Context<'_>) -> Poll<()> {
+    if self.pending.load(Ordering::SeqCst) {
+      return Poll::Ready(());
+    }
+
+    let mut inner = self.inner.lock().unwrap();
+
+    // checked again, a control frame may have been queued before the lock was taken
+    if self.pending.load(Ordering::SeqCst) {
+      return Poll::Ready(());
+    }
+
+    inner.writer = Some(cx.waker().clone());
+    Poll::Pending
+  }
+
+  /// Takes the queued control frames: the pong to send, if any, whether to send a ping and the
+  /// close frame to send, if any.
+  pub(crate) fn take(&self) -> (Option<Vec<u8>>, bool, Option<Close>) {
+    if !self.pending.swap(false, Ordering::SeqCst) {
+      return (None, false, None);
+    }
+
+    let mut inner = self.inner.lock().unwrap();
+    (
+      inner.pong.take(),
+      std::mem::take(&mut inner.ping),
+      inner.close.take(),
+    )
+  }
+}
@@ -0,0 +1,37 @@
+use std::sync::Arc;
+
+use crate::{Close, Role, WSocketError};
+
+/// How a connection has been closed, see [`WebSocket::closed`](crate::WebSocket::closed).
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Closed {
+  pub close: Close,
+  pub initiator: Initiator,
+}
+
+/// The endpoint that closed the connection.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Initiator {
+  /// Closed by calling [`close`](crate::WebSocket::close) or because of an error, e.g. a
+  /// protocol violation of the peer or a failing io.
+  Local,
+  /// The peer sent a close frame.
+  Remote,
+}
+
+/// Passed to the hook installed with [`WebSocket::with_event_hook`](crate::WebSocket::with_event_hook).
+#[derive(Debug, Copy, Clone)]
+pub enum Event<'a> {
+  /// The hook has been installed on an open connection.
+  Open(Role),
+  /// The connection has been closed, emitted once for both halves.
+  Close(&'a Closed),
+  /// Receiving or sending failed, followed by [`Event::Close`].
+  Error(&'a WSocketError),
+  /// A ping has been received.
+  Ping(&'a [u8]),
+  /// A pong has been received.
+  Pong(&'a [u8]),
+}
+
+pub(crate) type EventHook = Arc<dyn Fn(Event<'_>) + Send + Sync>;
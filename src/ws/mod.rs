@@ -1,78 +1,362 @@
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::fmt::Display;
+use std::future::{poll_fn, Future};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
 
-use tokio::io::{split, AsyncRead, AsyncWrite, ReadHalf, WriteHalf};
-use tokio::sync::broadcast;
+use control::ControlQueue;
+use event::EventHook;
+use signal::CloseSignal;
+use stats::{Direction, StatsCounters};
+use timeout::{ReadTimer, Timeouts};
+use tokio::io::AsyncWrite;
+use tracing::{debug, field, info_span, Span};
 
-use crate::Close;
+use crate::{
+  Close, Limits, Masking, OpCode, Protocol, Role, WSocketError, WSocketResult, WebSocketConfig,
+};
 
+mod control;
+mod event;
 mod read;
+#[cfg(feature = "sender")]
+mod sender;
+mod signal;
+mod split;
+mod stats;
+#[cfg(feature = "futures")]
+mod stream;
+#[cfg(test)]
+mod test;
+mod timeout;
 mod write;
 
+pub use event::{Closed, Event, Initiator};
+#[cfg(feature = "sender")]
+pub use sender::{OverflowPolicy, WebSocketSender};
+pub use split::{IntoSplit, ReuniteError};
+pub use stats::{ErrorStats, MessageStats, Stats, TrafficStats};
+
 pub struct WebSocket<IO> {
   io: IO,
-  max_payload_len: usize,
-  #[cfg(feature = "client")]
-  masking: bool,
-  closed: Arc<AtomicBool>,
-  close: broadcast::Sender<Close>,
+  protocol: Protocol,
+  write_high_water_mark: usize,
+  timeouts: Timeouts,
+  read_timer: ReadTimer,
+  auto_pong: bool,
+  control: Arc<ControlQueue>,
+  /// Writes the queued control frames while receiving, if the io is writable as well, which
+  /// isn't the case for the reading half of a split connection.
+  write_control: Option<WriteControl<IO>>,
+  closed: Arc<CloseSignal>,
+  hook: Option<EventHook>,
+  stats: Arc<StatsCounters>,
+  span: Span,
 }
 
-impl<IO> WebSocket<IO> {
+type WriteControl<IO> = fn(&mut WebSocket<IO>, &mut Context<'_>) -> Poll<WSocketResult<()>>;
+
+impl<IO: Unpin + AsyncWrite> WebSocket<IO> {
   #[inline]
-  pub fn server(io: IO, max_payload_len: usize) -> Self {
-    Self {
+  pub fn server(io: IO, config: WebSocketConfig) -> Self {
+    Self::from_config(
       io,
-      max_payload_len,
-      #[cfg(feature = "client")]
-      masking: false,
-      closed: Arc::new(AtomicBool::new(false)),
-      close: broadcast::Sender::new(1),
-    }
+      WebSocketConfig {
+        role: Role::Server,
+        ..config
+      },
+    )
   }
 
   #[inline]
   #[cfg(feature = "client")]
-  pub fn client(io: IO, max_payload_len: usize, masking: bool) -> Self {
+  pub fn client(io: IO, config: WebSocketConfig) -> Self {
+    Self::from_config(
+      io,
+      WebSocketConfig {
+        role: Role::Client,
+        ..config
+      },
+    )
+  }
+
+  /// Creates a connection acting as the [`role`](WebSocketConfig::role) of the config.
+  ///
+  /// Control frames, like pongs answering received pings or the close frame once receiving
+  /// failed, are written by the receiving methods as well, so the connection doesn't depend on
+  /// anything being sent.
+  pub fn from_config(io: IO, config: WebSocketConfig) -> Self {
+    let protocol = Protocol::new(config.role, config.read_limits)
+      .with_masking(config.masking)
+      .with_write_limits(config.write_limits)
+      .with_read_buffer_len(config.read_buffer_len);
+
     Self {
       io,
-      max_payload_len,
-      masking,
-      closed: Arc::new(AtomicBool::new(false)),
-      close: broadcast::Sender::new(1),
+      protocol,
+      write_high_water_mark: config.write_high_water_mark,
+      timeouts: Timeouts {
+        write: config.write_timeout,
+        frame: config.frame_timeout,
+        idle: config.idle_timeout,
+        close: config.close_timeout,
+        keepalive: config.keepalive,
+      },
+      read_timer: ReadTimer::default(),
+      auto_pong: config.auto_pong,
+      control: Arc::new(ControlQueue::default()),
+      write_control: Some(Self::poll_write_control),
+      closed: Arc::new(CloseSignal::new()),
+      hook: None,
+      stats: Arc::new(StatsCounters::default()),
+      span: connection_span(config.role),
     }
   }
+}
+
+impl<IO> WebSocket<IO> {
+  #[inline]
+  pub fn with_masking(mut self, masking: Masking) -> Self {
+    self.protocol = self.protocol.with_masking(masking);
+    self
+  }
+
+  /// Limits for received frames and messages, see [`Protocol::with_read_limits`].
+  #[inline]
+  pub fn with_read_limits(mut self, limits: Limits) -> Self {
+    self.protocol = self.protocol.with_read_limits(limits);
+    self
+  }
+
+  /// Limits for sent frames and messages, see [`Protocol::with_write_limits`].
+  #[inline]
+  pub fn with_write_limits(mut self, limits: Limits) -> Self {
+    self.protocol = self.protocol.with_write_limits(limits);
+    self
+  }
+
+  /// Reserved bits, that may be set in frames received using [`recv_frame`](Self::recv_frame),
+  /// e.g. `0b100` to allow `RSV1` if an extension using it has been negotiated.
+  #[inline]
+  pub fn with_allowed_rsv(mut self, allowed_rsv: u8) -> Self {
+    self.protocol = self.protocol.with_allowed_rsv(allowed_rsv);
+    self
+  }
+
+  /// Amount of bytes [fed](Self::feed) messages may occupy in the write buffer, before it is
+  /// written to the underlying io.
+  #[inline]
+  pub fn with_write_high_water_mark(mut self, write_high_water_mark: usize) -> Self {
+    self.write_high_water_mark = write_high_water_mark;
+    self
+  }
+
+  /// Fails sends with [`WSocketError::WriteTimeout`](crate::WSocketError::WriteTimeout), if the
+  /// peer doesn't take the data within `timeout`.
+  ///
+  /// Like all timeouts, this requires the timer of a tokio runtime.
+  #[inline]
+  pub fn with_write_timeout(mut self, timeout: Duration) -> Self {
+    self.timeouts.write = Some(timeout);
+    self
+  }
+
+  /// Fails receives with [`WSocketError::FrameTimeout`](crate::WSocketError::FrameTimeout), if a
+  /// frame that started arriving isn't complete within `timeout`, protecting against peers
+  /// trickling in frames byte by byte.
+  #[inline]
+  pub fn with_frame_timeout(mut self, timeout: Duration) -> Self {
+    self.timeouts.frame = Some(timeout);
+    self
+  }
+
+  /// Fails receives with [`WSocketError::IdleTimeout`](crate::WSocketError::IdleTimeout), if
+  /// nothing is received within `timeout` while waiting for the next frame.
+  #[inline]
+  pub fn with_idle_timeout(mut self, timeout: Duration) -> Self {
+    self.timeouts.idle = Some(timeout);
+    self
+  }
+
+  /// Limits how long [`close`](Self::close) may take to send the close frame, instead of the
+  /// write timeout.
+  #[inline]
+  pub fn with_close_timeout(mut self, timeout: Duration) -> Self {
+    self.timeouts.close = Some(timeout);
+    self
+  }
+
+  /// Sends a ping, once nothing has been received for `interval` while waiting for the next
+  /// frame, so idle connections are kept open and dead peers run into the idle timeout.
+  ///
+  /// Like pongs, the ping is written while receiving. The receiving half of a split connection
+  /// can't write, there the ping is sent by the next write or flush of the sending half.
+  #[inline]
+  pub fn with_keepalive(mut self, interval: Duration) -> Self {
+    self.timeouts.keepalive = Some(interval);
+    self
+  }
+
+  /// Whether received pings are answered with a pong, enabled by default. Pings are still returned
+  /// by [`recv`](Self::recv), the pong is written while waiting for the next frame.
+  ///
+  /// The receiving half of a split connection can't write, there the pong is sent by the next
+  /// write or flush of the sending half, so it has to be flushed regularly, even if nothing else
+  /// is sent.
+  #[inline]
+  pub fn with_auto_pong(mut self, auto_pong: bool) -> Self {
+    self.auto_pong = auto_pong;
+    self
+  }
+
+  /// Calls `hook` for events of this connection and of its halves, starting with
+  /// [`Event::Open`] right away. The hook is called while receiving or sending, so it should
+  /// return quickly.
+  pub fn with_event_hook(mut self, hook: impl Fn(Event<'_>) + Send + Sync + 'static) -> Self {
+    hook(Event::Open(self.role()));
+    self.hook = Some(Arc::new(hook));
+    self
+  }
+
+  /// Replaces the span all events of this connection are emitted in, e.g. with one that is a
+  /// child of the span of the request the connection has been upgraded from.
+  ///
+  /// The `peer` and `subprotocol` fields are only recorded, if the span declares them.
+  #[inline]
+  pub fn with_span(mut self, span: Span) -> Self {
+    self.span = span;
+    self
+  }
+
+  /// Records the address of the peer in the span of this connection.
+  #[inline]
+  pub fn with_peer(self, peer: impl Display) -> Self {
+    self.span.record("peer", field::display(peer));
+    self
+  }
+
+  /// Records the negotiated subprotocol in the span of this connection.
+  #[inline]
+  pub fn with_subprotocol(self, subprotocol: &str) -> Self {
+    self.span.record("subprotocol", subprotocol);
+    self
+  }
+
+  pub fn role(&self) -> Role {
+    self.protocol.role()
+  }
 
   pub fn is_closed(&self) -> bool {
-    self.closed.load(Ordering::SeqCst)
+    self.closed.is_closed()
   }
 
-  fn set_closed(&self, close: Close) {
-    self.closed.store(true, Ordering::SeqCst);
-    let _ = self.close.send(close);
+  /// Resolves once the connection is closed, no matter which half closed it. The future doesn't
+  /// borrow the connection, so it can be awaited by another task.
+  pub fn closed(&self) -> impl Future<Output = Closed> + Send + 'static {
+    let closed = self.closed.clone();
+    async move {
+      let mut waiter = closed.waiter();
+      poll_fn(|cx| waiter.poll_closed(cx)).await
+    }
   }
-}
 
-impl<IO: AsyncWrite + AsyncRead> WebSocket<IO> {
-  pub fn split(self) -> (WebSocket<ReadHalf<IO>>, WebSocket<WriteHalf<IO>>) {
-    let (read, write) = split(self.io);
-    (
-      WebSocket {
-        io: read,
-        max_payload_len: self.max_payload_len,
-        #[cfg(feature = "client")]
-        masking: self.masking,
-        closed: self.closed.clone(),
-        close: self.close.clone(),
-      },
-      WebSocket {
-        io: write,
-        max_payload_len: self.max_payload_len,
-        #[cfg(feature = "client")]
-        masking: self.masking,
-        closed: self.closed,
-        close: self.close,
-      },
-    )
+  /// Counters of this connection, shared by both halves of a split connection.
+  pub fn stats(&self) -> Stats {
+    self.stats.snapshot()
+  }
+
+  /// Span of this connection, with a process wide unique `id`, the role, the peer and the
+  /// subprotocol. Frames, handshake steps and the close reason are logged as debug events in it.
+  pub fn span(&self) -> &Span {
+    &self.span
+  }
+
+  pub fn get_ref(&self) -> &IO {
+    &self.io
   }
+
+  /// Reading from or writing to the io directly corrupts the connection, unless it is done in
+  /// between complete frames.
+  pub fn get_mut(&mut self) -> &mut IO {
+    &mut self.io
+  }
+
+  /// Returns the io, together with bytes already received from it, that haven't been decoded yet.
+  ///
+  /// Messages [fed](Self::feed) but not flushed yet are dropped.
+  pub fn into_inner(self) -> (IO, Vec<u8>) {
+    (self.io, self.protocol.into_input())
+  }
+
+  fn set_closed(&self, close: Close, initiator: Initiator) {
+    let closed = Closed { close, initiator };
+
+    if self.closed.close(closed.clone()) {
+      debug!(parent: &self.span, close = ?closed.close, initiator = ?initiator, "connection closed");
+      self.emit(Event::Close(&closed));
+    }
+  }
+
+  /// Counts and logs a message of `len` bytes, that has been split into `frames` frames.
+  fn record_message(&self, direction: Direction, opcode: OpCode, len: usize, frames: usize) {
+    record_message(&self.stats, &self.span, direction, opcode, len, frames);
+  }
+
+  /// Counts and logs a frame sent or received as is.
+  fn record_frame(&self, direction: Direction, opcode: OpCode, len: usize, fin: bool) {
+    record_frame(&self.stats, &self.span, direction, opcode, len, fin);
+  }
+
+  /// Counts the error failing the connection and reports it to the event hook.
+  fn report_error(&self, err: &WSocketError) {
+    debug!(parent: &self.span, %err, "connection failed");
+    self.stats.error(err);
+    self.emit(Event::Error(err));
+  }
+
+  fn emit(&self, event: Event<'_>) {
+    if let Some(hook) = &self.hook {
+      hook(event);
+    }
+  }
+}
+
+/// Same as [`WebSocket::record_message`], for when the connection is partly borrowed.
+fn record_message(
+  stats: &StatsCounters,
+  span: &Span,
+  direction: Direction,
+  opcode: OpCode,
+  len: usize,
+  frames: usize,
+) {
+  stats.message(direction, opcode, len, frames);
+  debug!(parent: span, ?direction, ?opcode, len, frames, "message");
+}
+
+/// Same as [`WebSocket::record_frame`], for when the connection is partly borrowed.
+fn record_frame(
+  stats: &StatsCounters,
+  span: &Span,
+  direction: Direction,
+  opcode: OpCode,
+  len: usize,
+  fin: bool,
+) {
+  stats.frame(direction);
+  debug!(parent: span, ?direction, ?opcode, len, fin, "frame");
+}
+
+/// Span of a new connection, see [`WebSocket::span`].
+pub(crate) fn connection_span(role: Role) -> Span {
+  static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+  info_span!(
+    "websocket",
+    id = NEXT_ID.fetch_add(1, Ordering::Relaxed),
+    role = ?role,
+    peer = field::Empty,
+    subprotocol = field::Empty,
+  )
 }
@@ -1,55 +1,373 @@
-use tokio::io::AsyncRead;
-use tokio::select;
-use tracing::info;
+use std::future::poll_fn;
+use std::io;
+use std::ops::Range;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
 
-use crate::frame::{Frame, OpCode};
-use crate::{Close, CloseCode};
+use tokio::io::{AsyncRead, ReadBuf};
+use tracing::{error, info};
+
+use super::event::{Event, Initiator};
+use super::stats::Direction;
+use super::timeout::write_timeout;
+use crate::protocol::Received;
+use crate::{Close, FrameHeader, OpCode, OwnedMessage};
 use crate::{Message, WSocketError, WSocketResult, WebSocket};
 
+/// All receiving methods are cancel safe: received bytes are kept in the [`Protocol`](crate::Protocol)
+/// until a complete frame is available, which is then taken out without any further await point.
+/// If a future is dropped, e.g. because another branch of a `select!` completed first, no frame is
+/// lost or torn apart and the next call continues where the dropped one stopped.
 impl<R: Unpin + AsyncRead> WebSocket<R> {
+  /// Receives the next message into `buf`, failing with [`WSocketError::BufferTooSmall`] if its
+  /// payload doesn't fit.
+  ///
+  /// # Cancel safety
+  ///
+  /// This method is cancel safe, a message is only taken out of the connection once it has been
+  /// copied into `buf`.
   pub async fn recv<'a>(&mut self, buf: &'a mut [u8]) -> WSocketResult<Message<'a>> {
     if self.is_closed() {
       return Err(WSocketError::NotConnected)?;
     }
 
-    let mut close = self.close.subscribe();
-
-    select! {
-      result = self.recv_message(buf) => {
-        match result {
-          Err(WSocketError::ConnectionClosed(ref close)) => {
-            info!("marking read channel as closed");
-            self.set_closed(close.clone());
-          },
-          Err(ref err) => {
-            let close = Close::new(
-              err.close_code().unwrap_or(CloseCode::InternalError),
-              Some(format!("{}", err))
-            );
-            self.set_closed(close);
-          },
+    let closed = self.closed.clone();
+    let result = closed.or_closed(self.recv_message(buf)).await;
+
+    match result {
+      Err(WSocketError::ConnectionClosed(_)) if closed.is_closed() => {}
+      Err(ref err) => {
+        self.on_recv_error(err);
+        self.send_close().await;
+      }
+      Ok(_) => {}
+    }
+
+    result
+  }
+
+  /// Receives the next message into a newly allocated buffer, which is sized to fit the payload
+  /// of the message, but never grows beyond the max message length of the read limits.
+  pub async fn recv_owned(&mut self) -> WSocketResult<OwnedMessage> {
+    if self.is_closed() {
+      return Err(WSocketError::NotConnected)?;
+    }
+
+    let closed = self.closed.clone();
+    let result = closed
+      .or_closed(poll_fn(|cx| self.poll_recv_owned(cx)))
+      .await;
+
+    if result.is_err() {
+      self.send_close().await;
+    }
+
+    result
+  }
+
+  /// Receives the next frame as is, without interpreting it. The payload is already unmasked, the
+  /// masking key is still part of the header.
+  ///
+  /// Reassembling fragmented messages and answering pings is up to the caller. Receiving a close
+  /// frame marks the connection as closed.
+  pub async fn recv_frame(&mut self) -> WSocketResult<(FrameHeader, &[u8])> {
+    if self.is_closed() {
+      return Err(WSocketError::NotConnected)?;
+    }
+
+    let closed = self.closed.clone();
+    let result = closed
+      .or_closed(poll_fn(|cx| self.poll_read_frame(cx)))
+      .await
+      .and_then(|(header, payload)| {
+        let data = self.protocol.payload(payload.clone());
+
+        match header.opcode {
+          OpCode::Close => self.set_closed(Close::parse(data)?, Initiator::Remote),
+          OpCode::Ping => self.emit(Event::Ping(data)),
+          OpCode::Pong => self.emit(Event::Pong(data)),
           _ => {}
         }
-        result
-      },
-      result = close.recv() => Err(WSocketError::ConnectionClosed(result.unwrap())),
+
+        Ok((header, payload))
+      });
+
+    match result {
+      Ok((header, payload)) => Ok((header, self.protocol.payload(payload))),
+      Err(err) => {
+        if !matches!(err, WSocketError::ConnectionClosed(_) if closed.is_closed()) {
+          self.on_recv_error(&err);
+          self.send_close().await;
+        }
+
+        Err(err)
+      }
+    }
+  }
+
+  /// Receives only the header of the next frame, so the caller can decide where to put its
+  /// payload based on opcode and length, before allocating anything.
+  ///
+  /// The payload is read using [`read_payload_into`](Self::read_payload_into) or dropped using
+  /// [`skip_payload`](Self::skip_payload). Payload left over is skipped, once the next frame is
+  /// received. Like [`recv_frame`](Self::recv_frame), nothing is reassembled or answered.
+  pub async fn read_header(&mut self) -> WSocketResult<FrameHeader> {
+    self.read_with(Self::poll_read_header).await
+  }
+
+  /// Reads the next part of the payload following [`read_header`](Self::read_header) into `buf`,
+  /// unmasked. Returns the amount of bytes read, `0` once the whole payload has been read.
+  pub async fn read_payload_into(&mut self, buf: &mut [u8]) -> WSocketResult<usize> {
+    self
+      .read_with(|ws, cx| ws.poll_read_payload_into(cx, buf))
+      .await
+  }
+
+  /// Drops the rest of the payload following [`read_header`](Self::read_header).
+  pub async fn skip_payload(&mut self) -> WSocketResult<()> {
+    self.read_with(Self::poll_skip_payload).await
+  }
+
+  /// Runs a poll based read, that is aborted if the connection gets closed by the other half.
+  /// The connection is closed if the read fails.
+  async fn read_with<T>(
+    &mut self,
+    mut poll: impl FnMut(&mut Self, &mut Context<'_>) -> Poll<WSocketResult<T>>,
+  ) -> WSocketResult<T> {
+    if self.is_closed() {
+      return Err(WSocketError::NotConnected)?;
+    }
+
+    let closed = self.closed.clone();
+    let result = closed.or_closed(poll_fn(|cx| poll(self, cx))).await;
+
+    match result {
+      Err(WSocketError::ConnectionClosed(_)) if closed.is_closed() => {}
+      Err(ref err) => {
+        self.on_recv_error(err);
+        self.send_close().await;
+      }
+      Ok(_) => {}
+    }
+
+    result
+  }
+
+  /// Poll based variant of [`recv_owned`](Self::recv_owned), that doesn't wait for the other half
+  /// of the connection to be closed.
+  pub(crate) fn poll_recv_owned(
+    &mut self,
+    cx: &mut Context<'_>,
+  ) -> Poll<WSocketResult<OwnedMessage>> {
+    let result = ready!(self.poll_read_message(cx))
+      .and_then(|received| self.protocol.message(received))
+      .inspect(|message| self.on_message(message))
+      .map(OwnedMessage::from);
+
+    if let Err(ref err) = result {
+      self.on_recv_error(err);
+
+      // the close frame is only started here, the rest is written by the next write or flush
+      if let Some(write_control) = self.write_control {
+        let _ = write_control(self, cx);
+      }
+    }
+
+    Poll::Ready(result)
+  }
+
+  /// Marks the connection as closed and queues the close frame for the writing side, echoing the
+  /// one of the peer or reporting the error, if it wasn't an io error.
+  fn on_recv_error(&self, err: &WSocketError) {
+    match err {
+      WSocketError::ConnectionClosed(close) => {
+        info!(parent: &self.span, "marking read channel as closed");
+        self.set_closed(close.clone(), Initiator::Remote);
+        self.control.close(close.reply());
+      }
+      err => {
+        let close = err.to_close();
+        self.report_error(err);
+        self.set_closed(close.clone(), Initiator::Local);
+
+        if !err.is_io_error() {
+          self.control.close(close);
+        }
+      }
+    }
+  }
+
+  /// Sends the close frame queued by [`on_recv_error`](Self::on_recv_error), if the io is
+  /// writable. Sending it may take up to the close timeout, falling back to the write timeout.
+  async fn send_close(&mut self) {
+    let Some(write_control) = self.write_control else {
+      return;
+    };
+
+    if !self.control.is_pending() && self.protocol.output().is_empty() {
+      return;
+    }
+
+    let timeout = self.timeouts.close.or(self.timeouts.write);
+
+    if let Err(err) = write_timeout(timeout, poll_fn(|cx| write_control(self, cx))).await {
+      error!(parent: &self.span, "Failed to send close frame: {}", err);
     }
   }
 
   async fn recv_message<'a>(&mut self, buf: &'a mut [u8]) -> WSocketResult<Message<'a>> {
-    let frame = Frame::read(&mut self.io, buf, self.max_payload_len).await?;
+    let received = poll_fn(|cx| self.poll_read_message(cx)).await?;
+
+    let message = self.protocol.message(received)?;
+    self.on_message(&message);
+    message.copy_into(buf)
+  }
+
+  /// Answers pings, if enabled.
+  fn on_message(&self, message: &Message<'_>) {
+    match *message {
+      Message::Ping(payload) => {
+        self.emit(Event::Ping(payload));
+
+        if self.auto_pong {
+          self.control.pong(payload);
+        }
+      }
+      Message::Pong(payload) => self.emit(Event::Pong(payload)),
+      _ => {}
+    }
+  }
+
+  /// Reads until the protocol was able to decode a complete message, reassembling fragments.
+  fn poll_read_message(&mut self, cx: &mut Context<'_>) -> Poll<WSocketResult<Received>> {
+    loop {
+      if let Some(received) = self.protocol.decode_message()? {
+        self.read_timer.on_frame();
+        self.record_received(&received);
+        return Poll::Ready(Ok(received));
+      }
+
+      ready!(self.poll_fill(cx))?;
+    }
+  }
+
+  fn record_received(&self, received: &Received) {
+    let len = self.protocol.message_len(received);
+
+    match *received {
+      Received::Frame(header, _) => self.record_message(Direction::Received, header.opcode, len, 1),
+      Received::Reassembled { opcode, fragments } => {
+        self.record_message(Direction::Received, opcode, len, fragments)
+      }
+    }
+  }
+
+  /// Reads until the protocol was able to decode a complete frame.
+  fn poll_read_frame(
+    &mut self,
+    cx: &mut Context<'_>,
+  ) -> Poll<WSocketResult<(FrameHeader, Range<usize>)>> {
+    loop {
+      if let Some((header, payload)) = self.protocol.decode()? {
+        self.read_timer.on_frame();
+        self.record_frame(Direction::Received, header.opcode, header.len, header.fin);
+        return Poll::Ready(Ok((header, payload)));
+      }
+
+      ready!(self.poll_fill(cx))?;
+    }
+  }
+
+  fn poll_read_header(&mut self, cx: &mut Context<'_>) -> Poll<WSocketResult<FrameHeader>> {
+    loop {
+      if let Some(header) = self.protocol.recv_header()? {
+        self.read_timer.on_frame();
+        self.record_frame(Direction::Received, header.opcode, header.len, header.fin);
+        return Poll::Ready(Ok(header));
+      }
+
+      ready!(self.poll_fill(cx))?;
+    }
+  }
+
+  fn poll_read_payload_into(
+    &mut self,
+    cx: &mut Context<'_>,
+    buf: &mut [u8],
+  ) -> Poll<WSocketResult<usize>> {
+    loop {
+      if buf.is_empty() || self.protocol.payload_remaining() == 0 {
+        return Poll::Ready(Ok(0));
+      }
+
+      match self.protocol.read_payload(buf) {
+        0 => ready!(self.poll_fill(cx))?,
+        len => return Poll::Ready(Ok(len)),
+      }
+    }
+  }
+
+  fn poll_skip_payload(&mut self, cx: &mut Context<'_>) -> Poll<WSocketResult<()>> {
+    while !self.protocol.skip_payload() {
+      ready!(self.poll_fill(cx))?;
+    }
+
+    Poll::Ready(Ok(()))
+  }
+
+  /// Reads received bytes into the input buffer of the protocol.
+  ///
+  /// Fails once the frame or idle timeout expires while waiting. Queues a keepalive ping, if
+  /// nothing has been received for the keepalive interval. Queued control frames are written
+  /// meanwhile, if the io is writable.
+  fn poll_fill(&mut self, cx: &mut Context<'_>) -> Poll<WSocketResult<()>> {
+    self.poll_send_control(cx)?;
+
+    let mut buf = ReadBuf::new(self.protocol.input_buf());
+
+    if Pin::new(&mut self.io).poll_read(cx, &mut buf)?.is_pending() {
+      let partial = self.protocol.is_receiving_frame();
+
+      if !partial
+        && self
+          .read_timer
+          .poll_keepalive(cx, &self.timeouts)
+          .is_ready()
+      {
+        self.control.ping();
+        // registers the restarted interval
+        let _ = self.read_timer.poll_keepalive(cx, &self.timeouts);
+        self.poll_send_control(cx)?;
+      }
+
+      let err = ready!(self.read_timer.poll_expired(cx, &self.timeouts, partial));
+      return Poll::Ready(Err(err));
+    }
+
+    match buf.filled().len() {
+      0 => Poll::Ready(Err(io::Error::from(io::ErrorKind::UnexpectedEof).into())),
+      len => {
+        self.protocol.commit_input(len);
+        self.read_timer.on_received();
+        Poll::Ready(Ok(()))
+      }
+    }
+  }
+
+  /// Writes the queued control frames, together with fed messages, if the io is writable. A
+  /// write that can't complete yet doesn't hold up receiving, it is continued by the next poll.
+  fn poll_send_control(&mut self, cx: &mut Context<'_>) -> WSocketResult<()> {
+    let Some(write_control) = self.write_control else {
+      return Ok(());
+    };
 
-    if !frame.fin {
-      return Err(WSocketError::FramedMessagesAreNotSupported);
+    if !self.control.is_pending() && self.protocol.output().is_empty() {
+      return Ok(());
     }
 
-    match frame.opcode {
-      OpCode::Continuation => Err(WSocketError::FramedMessagesAreNotSupported),
-      OpCode::Text => Err(WSocketError::TextFramesAreNotSupported),
-      OpCode::Binary => Ok(Message::Binary(frame.data)),
-      OpCode::Close => Err(WSocketError::ConnectionClosed(Close::parse(frame.data)?)),
-      OpCode::Ping => Ok(Message::Ping(frame.data)),
-      OpCode::Pong => Ok(Message::Pong(frame.data)),
+    match write_control(self, cx) {
+      Poll::Ready(result) => result,
+      Poll::Pending => Ok(()),
     }
   }
 }
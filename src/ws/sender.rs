@@ -0,0 +1,342 @@
+use std::collections::VecDeque;
+use std::future::{poll_fn, Future};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+
+use tokio::io::AsyncWrite;
+
+use crate::{Close, OwnedMessage, WSocketError, WSocketResult, WebSocket};
+
+/// What [`WebSocketSender::send`] does, if the queue of the writer task is full.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+#[cfg_attr(
+  feature = "serde",
+  derive(serde::Serialize, serde::Deserialize),
+  serde(rename_all = "snake_case")
+)]
+pub enum OverflowPolicy {
+  /// Waits until the writer task made room.
+  #[default]
+  Block,
+  /// Drops the oldest queued message to make room.
+  DropOldest,
+  /// Closes the connection with [`CloseCode::PolicyViolation`](crate::CloseCode::PolicyViolation),
+  /// dropping all queued messages. The send fails with [`WSocketError::QueueFull`].
+  Close,
+}
+
+/// Cloneable handle queueing messages for a writer task, see [`WebSocket::spawn_writer`].
+///
+/// Pings and pongs skip the queue of messages, so they are sent ahead of queued messages. Pings
+/// are queued separately, bounded and handled by the [`OverflowPolicy`] the same way, while only
+/// the most recent pong not sent yet is kept, as permitted by RFC 6455. The writer task stops once
+/// the connection is closed or all senders are dropped, echoing the close frame of the peer.
+pub struct WebSocketSender {
+  queue: Arc<SendQueue>,
+}
+
+impl WebSocketSender {
+  /// Queues the message, waiting for room according to the [`OverflowPolicy`]. Resolves once the
+  /// message is queued, not once it is sent.
+  ///
+  /// # Cancel safety
+  ///
+  /// This method is cancel safe, a cancelled message has not been queued.
+  pub async fn send(&self, message: OwnedMessage) -> WSocketResult<()> {
+    let mut message = Some(message);
+    poll_fn(|cx| self.queue.poll_push(cx, &mut message)).await
+  }
+
+  /// Sends the close frame after all messages queued so far. Later sends fail with
+  /// [`WSocketError::NotConnected`].
+  pub fn close(&self, close: Close) -> WSocketResult<()> {
+    let mut inner = self.queue.inner.lock().unwrap();
+
+    if inner.is_closed() {
+      return Err(WSocketError::NotConnected);
+    }
+
+    inner.close = Some(close);
+    inner.wake_writer();
+    Ok(())
+  }
+
+  pub fn is_closed(&self) -> bool {
+    self.queue.inner.lock().unwrap().is_closed()
+  }
+}
+
+impl Clone for WebSocketSender {
+  fn clone(&self) -> Self {
+    self.queue.inner.lock().unwrap().senders += 1;
+
+    Self {
+      queue: self.queue.clone(),
+    }
+  }
+}
+
+impl Drop for WebSocketSender {
+  fn drop(&mut self) {
+    let mut inner = self.queue.inner.lock().unwrap();
+    inner.senders -= 1;
+
+    if inner.senders == 0 {
+      inner.wake_writer();
+    }
+  }
+}
+
+/// Messages waiting for the writer task. Waiting only needs a [`Waker`], so the writer task can
+/// run on any runtime.
+struct SendQueue {
+  len: usize,
+  overflow: OverflowPolicy,
+  inner: Mutex<Inner>,
+}
+
+struct Inner {
+  /// Payloads of the pings to send ahead of queued messages.
+  pings: VecDeque<Vec<u8>>,
+  /// Payload of the pong to send ahead of queued messages, replaced by later pongs.
+  pong: Option<Vec<u8>>,
+  messages: VecDeque<OwnedMessage>,
+  close: Option<Close>,
+  senders: usize,
+  /// Set once the writer task stopped, e.g. because the connection has been closed.
+  stopped: bool,
+  writer: Option<Waker>,
+  blocked: Vec<Waker>,
+}
+
+/// What the writer task has to do next.
+enum Next {
+  Message(OwnedMessage),
+  /// Control frames have been queued by the reading half, e.g. answering pings.
+  Flush,
+  Close(Close),
+  /// The connection has been closed, the close frame queued by the reading half, e.g. answering
+  /// the one of the peer, has to be sent.
+  Closed,
+  Stop,
+}
+
+impl SendQueue {
+  fn new(len: usize, overflow: OverflowPolicy) -> Self {
+    Self {
+      len: len.max(1),
+      overflow,
+      inner: Mutex::new(Inner {
+        pings: VecDeque::new(),
+        pong: None,
+        messages: VecDeque::new(),
+        close: None,
+        senders: 1,
+        stopped: false,
+        writer: None,
+        blocked: Vec::new(),
+      }),
+    }
+  }
+
+  fn poll_push(
+    &self,
+    cx: &mut Context<'_>,
+    message: &mut Option<OwnedMessage>,
+  ) -> Poll<WSocketResult<()>> {
+    let mut inner = self.inner.lock().unwrap();
+
+    if inner.is_closed() {
+      return Poll::Ready(Err(WSocketError::NotConnected));
+    }
+
+    let queued = match message {
+      Some(OwnedMessage::Ping(_)) => inner.pings.len(),
+      Some(OwnedMessage::Pong(_)) => 0,
+      _ => inner.messages.len(),
+    };
+
+    if queued >= self.len {
+      match self.overflow {
+        OverflowPolicy::Block => {
+          if !inner
+            .blocked
+            .iter()
+            .any(|waker| waker.will_wake(cx.waker()))
+          {
+            inner.blocked.push(cx.waker().clone());
+          }
+
+          return Poll::Pending;
+        }
+        OverflowPolicy::DropOldest => {
+          if let Some(OwnedMessage::Ping(_)) = message {
+            inner.pings.pop_front();
+          } else {
+            inner.messages.pop_front();
+          }
+        }
+        OverflowPolicy::Close => {
+          inner.pings.clear();
+          inner.messages.clear();
+          inner.close = Some(WSocketError::QueueFull.to_close());
+          inner.wake_writer();
+          return Poll::Ready(Err(WSocketError::QueueFull));
+        }
+      }
+    }
+
+    match message.take().expect("message already queued") {
+      OwnedMessage::Ping(payload) => inner.pings.push_back(payload),
+      OwnedMessage::Pong(payload) => inner.pong = Some(payload),
+      message => inner.messages.push_back(message),
+    }
+
+    inner.wake_writer();
+    Poll::Ready(Ok(()))
+  }
+
+  /// Takes the next message, control frames first. The close frame follows all queued messages.
+  fn poll_next(&self, cx: &mut Context<'_>) -> Poll<Next> {
+    let mut inner = self.inner.lock().unwrap();
+
+    if let Some(payload) = inner.pong.take() {
+      return Poll::Ready(Next::Message(OwnedMessage::Pong(payload)));
+    }
+
+    let message = inner
+      .pings
+      .pop_front()
+      .map(OwnedMessage::Ping)
+      .or_else(|| inner.messages.pop_front());
+
+    if let Some(message) = message {
+      for waker in inner.blocked.drain(..) {
+        waker.wake();
+      }
+
+      return Poll::Ready(Next::Message(message));
+    }
+
+    if let Some(close) = inner.close.take() {
+      inner.stopped = true;
+      return Poll::Ready(Next::Close(close));
+    }
+
+    if inner.senders == 0 {
+      return Poll::Ready(Next::Stop);
+    }
+
+    inner.writer = Some(cx.waker().clone());
+    Poll::Pending
+  }
+
+  fn is_empty(&self) -> bool {
+    let inner = self.inner.lock().unwrap();
+    inner.pings.is_empty()
+      && inner.pong.is_none()
+      && inner.messages.is_empty()
+      && inner.close.is_none()
+  }
+
+  /// Fails all current and future sends, once the writer task stopped.
+  fn stop(&self) {
+    let mut inner = self.inner.lock().unwrap();
+    inner.stopped = true;
+    inner.pings.clear();
+    inner.pong = None;
+    inner.messages.clear();
+
+    for waker in inner.blocked.drain(..) {
+      waker.wake();
+    }
+  }
+}
+
+impl Inner {
+  fn is_closed(&self) -> bool {
+    self.stopped || self.close.is_some()
+  }
+
+  fn wake_writer(&mut self) {
+    if let Some(waker) = self.writer.take() {
+      waker.wake();
+    }
+  }
+}
+
+impl<W: Unpin + AsyncWrite> WebSocket<W> {
+  /// Spawns a tokio task owning this connection, which sends the messages queued through the
+  /// returned [`WebSocketSender`]. At most `queue_len` messages are queued, further sends are
+  /// handled according to `overflow`.
+  ///
+  /// The task also sends pongs and keepalive pings of the reading half right away, instead of
+  /// waiting for the next message.
+  pub fn spawn_writer(self, queue_len: usize, overflow: OverflowPolicy) -> WebSocketSender
+  where
+    W: Send + 'static,
+  {
+    let (sender, writer) = self.into_sender(queue_len, overflow);
+    tokio::spawn(writer);
+    sender
+  }
+
+  /// Same as [`spawn_writer`](Self::spawn_writer), but returns the writer task instead of
+  /// spawning it, so it can be run on any runtime.
+  pub fn into_sender(
+    self,
+    queue_len: usize,
+    overflow: OverflowPolicy,
+  ) -> (WebSocketSender, impl Future<Output = ()>) {
+    let queue = Arc::new(SendQueue::new(queue_len, overflow));
+    let sender = WebSocketSender {
+      queue: queue.clone(),
+    };
+
+    (sender, self.run_writer(queue))
+  }
+
+  async fn run_writer(mut self, queue: Arc<SendQueue>) {
+    let closed = self.closed.clone();
+    let mut waiter = closed.waiter();
+
+    loop {
+      let next = poll_fn(|cx| {
+        if waiter.poll_closed(cx).is_ready() {
+          return Poll::Ready(Next::Closed);
+        }
+
+        if let Poll::Ready(next) = queue.poll_next(cx) {
+          return Poll::Ready(next);
+        }
+
+        self.control.poll_pending(cx).map(|_| Next::Flush)
+      })
+      .await;
+
+      let result = match next {
+        // queued messages are coalesced, until the queue runs empty
+        Next::Message(message) => match self.feed(message.as_message()).await {
+          Ok(_) if queue.is_empty() => self.flush().await,
+          result => result,
+        },
+        Next::Flush => self.flush().await,
+        Next::Close(close) => {
+          let _ = self.close(close).await;
+          break;
+        }
+        Next::Closed => {
+          let _ = self.send_queued_close().await;
+          break;
+        }
+        Next::Stop => break,
+      };
+
+      if result.is_err() {
+        break;
+      }
+    }
+
+    queue.stop();
+  }
+}
@@ -0,0 +1,132 @@
+use std::future::{poll_fn, Future};
+use std::pin::pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::task::{Context, Poll, Waker};
+
+use super::event::Closed;
+use crate::{WSocketError, WSocketResult};
+
+/// Close state shared between both halves of a connection. Waiting for it only needs a [`Waker`],
+/// so it works with any runtime.
+pub(crate) struct CloseSignal {
+  closed: AtomicBool,
+  inner: Mutex<Inner>,
+}
+
+struct Inner {
+  closed: Option<Closed>,
+  /// Wakers of the pending [`CloseWaiter`]s, by their key.
+  wakers: Vec<(u64, Waker)>,
+  next_key: u64,
+}
+
+/// A task waiting for the connection to be closed. Its waker is removed once it's dropped, so
+/// cancelled waits don't pile up.
+pub(crate) struct CloseWaiter<'a> {
+  signal: &'a CloseSignal,
+  key: Option<u64>,
+}
+
+impl CloseSignal {
+  pub(crate) fn new() -> Self {
+    Self {
+      closed: AtomicBool::new(false),
+      inner: Mutex::new(Inner {
+        closed: None,
+        wakers: Vec::new(),
+        next_key: 0,
+      }),
+    }
+  }
+
+  pub(crate) fn is_closed(&self) -> bool {
+    self.closed.load(Ordering::SeqCst)
+  }
+
+  /// Marks the connection as closed and wakes everyone waiting for it. Only the first close is
+  /// kept, later calls just mark the connection as closed and return `false`.
+  pub(crate) fn close(&self, closed: Closed) -> bool {
+    self.closed.store(true, Ordering::SeqCst);
+
+    let mut inner = self.inner.lock().unwrap();
+
+    if inner.closed.is_some() {
+      return false;
+    }
+
+    inner.closed = Some(closed);
+
+    for (_, waker) in inner.wakers.drain(..) {
+      waker.wake();
+    }
+
+    true
+  }
+
+  pub(crate) fn waiter(&self) -> CloseWaiter<'_> {
+    CloseWaiter {
+      signal: self,
+      key: None,
+    }
+  }
+
+  /// Amount of registered wakers.
+  #[cfg(test)]
+  pub(crate) fn waiters(&self) -> usize {
+    self.inner.lock().unwrap().wakers.len()
+  }
+
+  /// Resolves with the result of `future`, unless the connection gets closed first.
+  pub(crate) async fn or_closed<T>(
+    &self,
+    future: impl Future<Output = WSocketResult<T>>,
+  ) -> WSocketResult<T> {
+    let mut future = pin!(future);
+    let mut waiter = self.waiter();
+
+    poll_fn(|cx| {
+      if let Poll::Ready(closed) = waiter.poll_closed(cx) {
+        return Poll::Ready(Err(WSocketError::ConnectionClosed(closed.close)));
+      }
+
+      future.as_mut().poll(cx)
+    })
+    .await
+  }
+}
+
+impl CloseWaiter<'_> {
+  pub(crate) fn poll_closed(&mut self, cx: &mut Context<'_>) -> Poll<Closed> {
+    let mut inner = self.signal.inner.lock().unwrap();
+
+    if let Some(closed) = &inner.closed {
+      return Poll::Ready(closed.clone());
+    }
+
+    let registered = self
+      .key
+      .and_then(|key| inner.wakers.iter_mut().find(|(other, _)| *other == key));
+
+    match registered {
+      Some((_, waker)) => waker.clone_from(cx.waker()),
+      None => {
+        let key = inner.next_key;
+        inner.next_key += 1;
+        inner.wakers.push((key, cx.waker().clone()));
+        self.key = Some(key);
+      }
+    }
+
+    Poll::Pending
+  }
+}
+
+impl Drop for CloseWaiter<'_> {
+  fn drop(&mut self) {
+    if let Some(key) = self.key {
+      let mut inner = self.signal.inner.lock().unwrap();
+      inner.wakers.retain(|(other, _)| *other != key);
+    }
+  }
+}
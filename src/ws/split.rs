@@ -0,0 +1,144 @@
+use std::fmt;
+
+use tokio::io::{split, AsyncRead, AsyncWrite, ReadHalf, WriteHalf};
+
+use super::timeout::{ReadTimer, Timeouts};
+use crate::{Protocol, WebSocket};
+
+/// Io that can be split into independently owned read and write halves, like tokio's
+/// `TcpStream::into_split`. Implemented for `TcpStream` and `UnixStream` with the `net` feature.
+///
+/// Unlike [`tokio::io::split`], the halves don't share a lock, so reading and writing never
+/// contend. Implement it for custom transports to use them with [`WebSocket::into_split`].
+pub trait IntoSplit: AsyncRead + AsyncWrite {
+  type ReadHalf: AsyncRead;
+  type WriteHalf: AsyncWrite;
+
+  fn into_split(self) -> (Self::ReadHalf, Self::WriteHalf);
+}
+
+#[cfg(feature = "net")]
+impl IntoSplit for tokio::net::TcpStream {
+  type ReadHalf = tokio::net::tcp::OwnedReadHalf;
+  type WriteHalf = tokio::net::tcp::OwnedWriteHalf;
+
+  fn into_split(self) -> (Self::ReadHalf, Self::WriteHalf) {
+    tokio::net::TcpStream::into_split(self)
+  }
+}
+
+#[cfg(all(feature = "net", unix))]
+impl IntoSplit for tokio::net::UnixStream {
+  type ReadHalf = tokio::net::unix::OwnedReadHalf;
+  type WriteHalf = tokio::net::unix::OwnedWriteHalf;
+
+  fn into_split(self) -> (Self::ReadHalf, Self::WriteHalf) {
+    tokio::net::UnixStream::into_split(self)
+  }
+}
+
+impl<IO: AsyncWrite + AsyncRead> WebSocket<IO> {
+  /// Splits into a half only used for receiving and one only used for sending, sharing the io
+  /// through [`tokio::io::split`]. Closing either half closes the other one as well.
+  ///
+  /// The receiving half can't write, so the close frame answering the one of the peer or
+  /// reporting a failed receive is sent by the next write, flush or close of the sending half.
+  pub fn split(self) -> (WebSocket<ReadHalf<IO>>, WebSocket<WriteHalf<IO>>) {
+    self.split_with(split)
+  }
+}
+
+impl<IO: IntoSplit> WebSocket<IO> {
+  /// Same as [`split`](Self::split), but using the owned halves of the io, so receiving and
+  /// sending never wait for each other.
+  pub fn into_split(self) -> (WebSocket<IO::ReadHalf>, WebSocket<IO::WriteHalf>) {
+    self.split_with(IO::into_split)
+  }
+}
+
+impl<IO> WebSocket<IO> {
+  /// Splits the connection, using `split` to split the io. Both halves share the close state.
+  fn split_with<R, W>(self, split: impl FnOnce(IO) -> (R, W)) -> (WebSocket<R>, WebSocket<W>) {
+    let (read, write) = split(self.io);
+    let (read_protocol, write_protocol) = self.protocol.split();
+    (
+      WebSocket {
+        io: read,
+        protocol: read_protocol,
+        write_high_water_mark: self.write_high_water_mark,
+        timeouts: self.timeouts,
+        read_timer: self.read_timer,
+        auto_pong: self.auto_pong,
+        control: self.control.clone(),
+        write_control: None,
+        closed: self.closed.clone(),
+        hook: self.hook.clone(),
+        stats: self.stats.clone(),
+        span: self.span.clone(),
+      },
+      WebSocket {
+        io: write,
+        protocol: write_protocol,
+        write_high_water_mark: self.write_high_water_mark,
+        timeouts: self.timeouts,
+        read_timer: ReadTimer::default(),
+        auto_pong: self.auto_pong,
+        control: self.control,
+        write_control: None,
+        closed: self.closed,
+        hook: self.hook,
+        stats: self.stats,
+        span: self.span,
+      },
+    )
+  }
+}
+
+impl<IO: Unpin + AsyncWrite> WebSocket<ReadHalf<IO>> {
+  /// Joins the halves returned by [`split`](WebSocket::split) again, failing if they come from
+  /// different connections. Received bytes and fed messages of both halves are kept.
+  pub fn reunite(self, write: WebSocket<WriteHalf<IO>>) -> Result<WebSocket<IO>, ReuniteError<IO>> {
+    if !self.io.is_pair_of(&write.io) {
+      return Err(ReuniteError(Box::new(self), Box::new(write)));
+    }
+
+    Ok(WebSocket {
+      io: self.io.unsplit(write.io),
+      protocol: Protocol::unsplit(self.protocol, write.protocol),
+      write_high_water_mark: write.write_high_water_mark,
+      timeouts: Timeouts {
+        write: write.timeouts.write,
+        close: write.timeouts.close,
+        ..self.timeouts
+      },
+      read_timer: self.read_timer,
+      auto_pong: self.auto_pong,
+      control: self.control,
+      write_control: Some(WebSocket::poll_write_control),
+      closed: self.closed,
+      hook: self.hook,
+      stats: self.stats,
+      span: self.span,
+    })
+  }
+}
+
+/// The halves passed to [`WebSocket::reunite`], which belong to different connections.
+pub struct ReuniteError<IO>(
+  pub Box<WebSocket<ReadHalf<IO>>>,
+  pub Box<WebSocket<WriteHalf<IO>>>,
+);
+
+impl<IO> fmt::Debug for ReuniteError<IO> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_tuple("ReuniteError").finish_non_exhaustive()
+  }
+}
+
+impl<IO> fmt::Display for ReuniteError<IO> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str("tried to reunite halves of different connections")
+  }
+}
+
+impl<IO> std::error::Error for ReuniteError<IO> {}
@@ -0,0 +1,244 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::{CloseCode, OpCode, WSocketError};
+
+/// Snapshot of the counters of a connection, see [`WebSocket::stats`](crate::WebSocket::stats).
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Stats {
+  pub sent: TrafficStats,
+  pub received: TrafficStats,
+  pub errors: ErrorStats,
+}
+
+/// Messages and frames of one direction. Frames sent or received as is, e.g. using
+/// [`send_frame`](crate::WebSocket::send_frame) or [`recv_frame`](crate::WebSocket::recv_frame),
+/// only count as frames.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct TrafficStats {
+  pub text: MessageStats,
+  pub binary: MessageStats,
+  pub ping: MessageStats,
+  pub pong: MessageStats,
+  pub close: MessageStats,
+  /// All frames, including control frames and fragments.
+  pub frames: u64,
+  /// Frames of fragmented messages.
+  pub fragments: u64,
+}
+
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct MessageStats {
+  pub messages: u64,
+  /// Payload bytes, without frame headers.
+  pub bytes: u64,
+}
+
+/// Errors that failed the connection, by kind.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct ErrorStats {
+  /// The peer violated the protocol, e.g. by sending invalid frames or invalid utf8.
+  pub protocol: u64,
+  /// A frame or message exceeded the limits.
+  pub too_big: u64,
+  pub timeout: u64,
+  pub io: u64,
+  pub other: u64,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub(crate) enum Direction {
+  Sent,
+  Received,
+}
+
+#[derive(Debug, Copy, Clone)]
+enum ErrorKind {
+  Protocol,
+  TooBig,
+  Timeout,
+  Io,
+  Other,
+}
+
+/// Counters shared between both halves of a connection. With the `metrics` feature, everything
+/// counted is recorded with the [`metrics`] crate as well, aggregated over all connections.
+#[derive(Default)]
+pub(crate) struct StatsCounters {
+  sent: TrafficCounters,
+  received: TrafficCounters,
+  errors: [AtomicU64; 5],
+}
+
+/// Messages and bytes are indexed by [`message_index`].
+#[derive(Default)]
+struct TrafficCounters {
+  messages: [AtomicU64; 5],
+  bytes: [AtomicU64; 5],
+  frames: AtomicU64,
+  fragments: AtomicU64,
+}
+
+impl StatsCounters {
+  /// Counts a message of `len` bytes, that has been split into `frames` frames.
+  pub(crate) fn message(&self, direction: Direction, opcode: OpCode, len: usize, frames: usize) {
+    let traffic = self.traffic(direction);
+    let fragments = if frames > 1 { frames } else { 0 };
+
+    if let Some(index) = message_index(opcode) {
+      traffic.messages[index].fetch_add(1, Ordering::Relaxed);
+      traffic.bytes[index].fetch_add(len as u64, Ordering::Relaxed);
+    }
+
+    traffic.frames.fetch_add(frames as u64, Ordering::Relaxed);
+    traffic
+      .fragments
+      .fetch_add(fragments as u64, Ordering::Relaxed);
+
+    #[cfg(feature = "metrics")]
+    {
+      let direction = direction.label();
+
+      if let Some(opcode) = opcode_label(opcode) {
+        metrics::counter!("wsocket_messages_total", "direction" => direction, "opcode" => opcode)
+          .increment(1);
+        metrics::counter!("wsocket_message_bytes_total", "direction" => direction, "opcode" => opcode)
+          .increment(len as u64);
+      }
+
+      metrics::counter!("wsocket_frames_total", "direction" => direction).increment(frames as u64);
+      metrics::counter!("wsocket_fragments_total", "direction" => direction)
+        .increment(fragments as u64);
+    }
+  }
+
+  /// Counts a frame sent or received as is.
+  pub(crate) fn frame(&self, direction: Direction) {
+    self
+      .traffic(direction)
+      .frames
+      .fetch_add(1, Ordering::Relaxed);
+
+    #[cfg(feature = "metrics")]
+    metrics::counter!("wsocket_frames_total", "direction" => direction.label()).increment(1);
+  }
+
+  pub(crate) fn error(&self, err: &WSocketError) {
+    let kind = ErrorKind::of(err);
+    self.errors[kind as usize].fetch_add(1, Ordering::Relaxed);
+
+    #[cfg(feature = "metrics")]
+    metrics::counter!("wsocket_errors_total", "kind" => kind.label()).increment(1);
+  }
+
+  pub(crate) fn snapshot(&self) -> Stats {
+    let errors = |kind: ErrorKind| self.errors[kind as usize].load(Ordering::Relaxed);
+
+    Stats {
+      sent: self.sent.snapshot(),
+      received: self.received.snapshot(),
+      errors: ErrorStats {
+        protocol: errors(ErrorKind::Protocol),
+        too_big: errors(ErrorKind::TooBig),
+        timeout: errors(ErrorKind::Timeout),
+        io: errors(ErrorKind::Io),
+        other: errors(ErrorKind::Other),
+      },
+    }
+  }
+
+  fn traffic(&self, direction: Direction) -> &TrafficCounters {
+    match direction {
+      Direction::Sent => &self.sent,
+      Direction::Received => &self.received,
+    }
+  }
+}
+
+impl TrafficCounters {
+  fn snapshot(&self) -> TrafficStats {
+    let message = |opcode: OpCode| {
+      let index = message_index(opcode).unwrap();
+
+      MessageStats {
+        messages: self.messages[index].load(Ordering::Relaxed),
+        bytes: self.bytes[index].load(Ordering::Relaxed),
+      }
+    };
+
+    TrafficStats {
+      text: message(OpCode::Text),
+      binary: message(OpCode::Binary),
+      ping: message(OpCode::Ping),
+      pong: message(OpCode::Pong),
+      close: message(OpCode::Close),
+      frames: self.frames.load(Ordering::Relaxed),
+      fragments: self.fragments.load(Ordering::Relaxed),
+    }
+  }
+}
+
+fn message_index(opcode: OpCode) -> Option<usize> {
+  match opcode {
+    OpCode::Continuation => None,
+    OpCode::Text => Some(0),
+    OpCode::Binary => Some(1),
+    OpCode::Ping => Some(2),
+    OpCode::Pong => Some(3),
+    OpCode::Close => Some(4),
+  }
+}
+
+impl ErrorKind {
+  fn of(err: &WSocketError) -> Self {
+    match err {
+      WSocketError::Io(_) => Self::Io,
+      WSocketError::WriteTimeout | WSocketError::FrameTimeout | WSocketError::IdleTimeout => {
+        Self::Timeout
+      }
+      err => match err.close_code() {
+        Some(CloseCode::MessageTooBig) => Self::TooBig,
+        Some(CloseCode::ProtocolError | CloseCode::Unsupported | CloseCode::InvalidPayload) => {
+          Self::Protocol
+        }
+        _ => Self::Other,
+      },
+    }
+  }
+
+  #[cfg(feature = "metrics")]
+  fn label(self) -> &'static str {
+    match self {
+      Self::Protocol => "protocol",
+      Self::TooBig => "too_big",
+      Self::Timeout => "timeout",
+      Self::Io => "io",
+      Self::Other => "other",
+    }
+  }
+}
+
+#[cfg(feature = "metrics")]
+impl Direction {
+  fn label(self) -> &'static str {
+    match self {
+      Self::Sent => "sent",
+      Self::Received => "received",
+    }
+  }
+}
+
+#[cfg(feature = "metrics")]
+fn opcode_label(opcode: OpCode) -> Option<&'static str> {
+  match opcode {
+    OpCode::Continuation => None,
+    OpCode::Text => Some("text"),
+    OpCode::Binary => Some("binary"),
+    OpCode::Ping => Some("ping"),
+    OpCode::Pong => Some("pong"),
+    OpCode::Close => Some("close"),
+  }
+}
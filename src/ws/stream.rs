@@ -0,0 +1,96 @@
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+
+use futures_core::Stream;
+use futures_sink::Sink;
+use tokio::io::{AsyncRead, AsyncWrite};
+
+use super::event::Initiator;
+use crate::{Close, CloseCode, Frame, OwnedMessage, WSocketError, WSocketResult, WebSocket};
+
+/// Yields received messages until the connection is closed. The error closing the connection is
+/// yielded once, all polls afterwards return [`None`].
+impl<R: Unpin + AsyncRead> Stream for WebSocket<R> {
+  type Item = WSocketResult<OwnedMessage>;
+
+  fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+    let this = self.get_mut();
+
+    if this.is_closed() {
+      return Poll::Ready(None);
+    }
+
+    this.poll_recv_owned(cx).map(Some)
+  }
+}
+
+/// Messages are queued in the write buffer, which is written once it exceeds the write high-water
+/// mark or the sink is flushed. Closing the sink sends a [`CloseCode::Normal`] close frame, if the
+/// connection hasn't been closed yet, and shuts down the underlying io.
+impl<W: Unpin + AsyncWrite> Sink<OwnedMessage> for WebSocket<W> {
+  type Error = WSocketError;
+
+  fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+    let this = self.get_mut();
+
+    if this.is_closed() {
+      return Poll::Ready(Err(WSocketError::NotConnected));
+    }
+
+    if this.protocol.output().len() >= this.write_high_water_mark {
+      ready!(this.poll_write_buffered(cx)).inspect_err(|err| this.on_sink_error(err))?;
+    }
+
+    Poll::Ready(Ok(()))
+  }
+
+  fn start_send(self: Pin<&mut Self>, item: OwnedMessage) -> Result<(), Self::Error> {
+    let this = self.get_mut();
+
+    if this.is_closed() {
+      return Err(WSocketError::NotConnected);
+    }
+
+    this
+      .encode_control()
+      .and_then(|_| this.encode_message(Frame::from(item.as_message())))
+      .inspect_err(|err| this.on_sink_error(err))
+  }
+
+  fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+    let this = self.get_mut();
+
+    if let Err(err) = this.encode_control() {
+      this.on_sink_error(&err);
+      return Poll::Ready(Err(err));
+    }
+
+    let result = ready!(this.poll_flush_frames(cx));
+    Poll::Ready(result.inspect_err(|err| this.on_sink_error(err)))
+  }
+
+  fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+    let this = self.get_mut();
+
+    if !this.is_closed() {
+      this.queue_close(Close::new(CloseCode::Normal, None))?;
+    }
+
+    ready!(this.poll_flush_frames(cx))?;
+    ready!(Pin::new(&mut this.io).poll_shutdown(cx))?;
+
+    Poll::Ready(Ok(()))
+  }
+}
+
+impl<W: Unpin + AsyncWrite> WebSocket<W> {
+  /// Same as `on_send_error`, but the close frame is only queued, to be written by the next flush.
+  fn on_sink_error(&mut self, err: &WSocketError) {
+    let close = err.to_close();
+    self.report_error(err);
+
+    if err.is_io_error() || self.queue_close(close.clone()).is_err() {
+      self.set_closed(close, Initiator::Local);
+    }
+  }
+}
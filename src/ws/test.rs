@@ -0,0 +1,1235 @@
+use std::future::Future;
+use std::io::{Cursor, IoSlice};
+use std::pin::{pin, Pin};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::time::Duration;
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf};
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Metadata, Subscriber};
+
+use crate::{
+  CloseCode, Frame, Limits, Masking, Message, OpCode, OwnedMessage, ReuniteError, WSocketError,
+  WSocketResult, WebSocket, WebSocketConfig,
+};
+
+fn config(max_payload_len: usize) -> WebSocketConfig {
+  WebSocketConfig {
+    read_limits: Limits::new(max_payload_len),
+    write_limits: Limits::new(max_payload_len),
+    ..WebSocketConfig::default()
+  }
+}
+
+#[tokio::test]
+async fn test_recv_owned_masked_ping() -> WSocketResult<()> {
+  let input = [
+    0x89, 0x85, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d, 0x51, 0x58,
+  ];
+  let mut ws = WebSocket::server(Cursor::new(input.to_vec()), config(5));
+
+  let message = ws.recv_owned().await?;
+  assert_eq!(message, OwnedMessage::Ping(b"Hello".to_vec()));
+
+  Ok(())
+}
+
+#[tokio::test]
+async fn test_recv_owned_grows_beyond_initial_buffer() -> WSocketResult<()> {
+  let input = include_bytes!("../test/frame_65536_in.bin");
+  let mut ws =
+    WebSocket::server(Cursor::new(input.to_vec()), config(65536)).with_masking(Masking::Lenient);
+
+  let message = ws.recv_owned().await?;
+  assert_eq!(
+    message,
+    OwnedMessage::Binary(include_bytes!("../test/frame_65536_out.bin").to_vec())
+  );
+
+  Ok(())
+}
+
+#[tokio::test]
+async fn test_recv_owned_payload_too_large() {
+  let input = [0x82, 0x05, 0x48, 0x65, 0x6c, 0x6c, 0x6f];
+  let mut ws =
+    WebSocket::server(Cursor::new(input.to_vec()), config(4)).with_masking(Masking::Lenient);
+
+  let result = ws.recv_owned().await;
+  assert!(matches!(result, Err(WSocketError::PayloadTooLarge)));
+  assert!(ws.is_closed());
+}
+
+#[tokio::test]
+async fn test_recv_buffer_smaller_than_payload() {
+  let input = [0x82, 0x05, 0x48, 0x65, 0x6c, 0x6c, 0x6f];
+  let mut ws =
+    WebSocket::server(Cursor::new(input.to_vec()), config(1024)).with_masking(Masking::Lenient);
+
+  let mut buf = [0u8; 4];
+  let result = ws.recv(&mut buf).await;
+  assert!(matches!(result, Err(WSocketError::BufferTooSmall(5))));
+  assert!(ws.is_closed());
+}
+
+#[tokio::test]
+async fn test_server_rejects_unmasked_frame() {
+  let input = [0x82, 0x05, 0x48, 0x65, 0x6c, 0x6c, 0x6f];
+  let mut ws = WebSocket::server(Cursor::new(input.to_vec()), config(1024));
+
+  let result = ws.recv_owned().await;
+  assert!(matches!(result, Err(WSocketError::FrameMustBeMasked)));
+  assert!(ws.is_closed());
+}
+
+#[cfg(feature = "client")]
+#[tokio::test]
+async fn test_client_rejects_masked_frame() {
+  let input = [
+    0x82, 0x85, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d, 0x51, 0x58,
+  ];
+  let mut ws = WebSocket::client(Cursor::new(input.to_vec()), config(1024));
+
+  let result = ws.recv_owned().await;
+  assert!(matches!(result, Err(WSocketError::FrameMustNotBeMasked)));
+  assert!(ws.is_closed());
+}
+
+#[cfg(feature = "client")]
+#[tokio::test]
+async fn test_lenient_client_accepts_masked_frame() -> WSocketResult<()> {
+  let input = [
+    0x82, 0x85, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d, 0x51, 0x58,
+  ];
+  let mut ws =
+    WebSocket::client(Cursor::new(input.to_vec()), config(1024)).with_masking(Masking::Lenient);
+
+  let message = ws.recv_owned().await?;
+  assert_eq!(message, OwnedMessage::Binary(b"Hello".to_vec()));
+
+  Ok(())
+}
+
+#[tokio::test]
+async fn test_feed_buffers_until_flush() -> WSocketResult<()> {
+  let mut ws = WebSocket::server(Vec::new(), config(1024));
+
+  ws.feed(Message::Ping(b"Hello")).await?;
+  ws.feed(Message::Binary(b"Hello")).await?;
+  assert!(ws.io.is_empty());
+
+  ws.flush().await?;
+  assert_eq!(
+    ws.io,
+    [0x89, 0x05, 0x48, 0x65, 0x6c, 0x6c, 0x6f, 0x82, 0x05, 0x48, 0x65, 0x6c, 0x6c, 0x6f]
+  );
+
+  Ok(())
+}
+
+#[tokio::test]
+async fn test_feed_writes_above_high_water_mark() -> WSocketResult<()> {
+  let mut ws = WebSocket::server(Vec::new(), config(1024)).with_write_high_water_mark(10);
+
+  ws.feed(Message::Binary(b"Hello")).await?;
+  assert!(ws.io.is_empty());
+
+  ws.feed(Message::Binary(b"Hello")).await?;
+  assert_eq!(ws.io.len(), 14);
+
+  Ok(())
+}
+
+#[tokio::test]
+async fn test_send_flushes() -> WSocketResult<()> {
+  let mut ws = WebSocket::server(Vec::new(), config(1024));
+
+  ws.send(Message::Binary(b"Hello")).await?;
+  assert_eq!(ws.io, [0x82, 0x05, 0x48, 0x65, 0x6c, 0x6c, 0x6f]);
+
+  Ok(())
+}
+
+#[cfg(feature = "futures")]
+#[tokio::test]
+async fn test_stream_and_sink() -> WSocketResult<()> {
+  use futures_util::{SinkExt, StreamExt};
+
+  use crate::Close;
+
+  let (a, b) = tokio::io::duplex(1024);
+  let mut tx = WebSocket::server(a, config(1024));
+  let mut rx = WebSocket::server(b, config(1024)).with_masking(Masking::Lenient);
+
+  SinkExt::feed(&mut tx, OwnedMessage::Binary(b"Hello".to_vec())).await?;
+  SinkExt::feed(&mut tx, OwnedMessage::Ping(b"Hello".to_vec())).await?;
+  SinkExt::close(&mut tx).await?;
+  assert!(tx.is_closed());
+
+  assert_eq!(
+    rx.next().await.transpose()?,
+    Some(OwnedMessage::Binary(b"Hello".to_vec()))
+  );
+  assert_eq!(
+    rx.next().await.transpose()?,
+    Some(OwnedMessage::Ping(b"Hello".to_vec()))
+  );
+  assert!(matches!(
+    rx.next().await,
+    Some(Err(WSocketError::ConnectionClosed(close))) if close == Close::new(CloseCode::Normal, None)
+  ));
+  assert!(rx.next().await.is_none());
+
+  Ok(())
+}
+
+#[tokio::test]
+async fn test_close_wakes_pending_recv_of_other_half() -> WSocketResult<()> {
+  use crate::Close;
+
+  let (io, _peer) = tokio::io::duplex(1024);
+  let (mut read, mut write) = WebSocket::server(io, config(1024)).split();
+
+  let (result, close) = tokio::join!(read.recv_owned(), async {
+    tokio::task::yield_now().await;
+    write.close(Close::new(CloseCode::Normal, None)).await
+  });
+  close?;
+
+  assert!(matches!(
+    result,
+    Err(WSocketError::ConnectionClosed(close)) if close == Close::new(CloseCode::Normal, None)
+  ));
+  assert!(read.is_closed());
+
+  Ok(())
+}
+
+#[tokio::test]
+async fn test_closed_reports_initiator() -> WSocketResult<()> {
+  use crate::{Close, Closed, Initiator};
+
+  let (io, peer) = tokio::io::duplex(1024);
+  let mut ws = WebSocket::server(io, config(1024)).with_masking(Masking::Lenient);
+  let mut peer = WebSocket::server(peer, config(1024)).with_masking(Masking::Lenient);
+
+  let closed = tokio::spawn(ws.closed());
+  let peer_closed = peer.closed();
+
+  peer.close(Close::new(CloseCode::Normal, None)).await?;
+  assert!(ws.recv_owned().await.is_err());
+
+  assert_eq!(
+    closed.await.unwrap(),
+    Closed {
+      close: Close::new(CloseCode::Normal, None),
+      initiator: Initiator::Remote,
+    }
+  );
+  assert_eq!(peer_closed.await.initiator, Initiator::Local);
+
+  Ok(())
+}
+
+#[tokio::test]
+async fn test_cancelled_waits_for_close_are_removed() {
+  let (io, _peer) = tokio::io::duplex(1024);
+  let mut ws = WebSocket::server(io, config(1024));
+
+  for _ in 0..8 {
+    assert!(!poll_and_cancel(ws.closed(), 1));
+    assert!(!poll_and_cancel(ws.recv_owned(), 1));
+  }
+
+  assert_eq!(ws.closed.waiters(), 0);
+}
+
+#[tokio::test]
+async fn test_event_hook() {
+  use crate::{Event, Initiator};
+
+  let events = Arc::new(Mutex::new(Vec::new()));
+  let input = [0x89, 0x02, 0x68, 0x69, 0x8a, 0x00];
+  let mut ws = WebSocket::server(Cursor::new(input.to_vec()), config(1024))
+    .with_masking(Masking::Lenient)
+    .with_event_hook({
+      let events = events.clone();
+      move |event| {
+        events.lock().unwrap().push(match event {
+          Event::Open(role) => format!("open {role:?}"),
+          Event::Close(closed) => format!("close {:?}", closed.initiator),
+          Event::Error(err) => format!("error {err}"),
+          Event::Ping(payload) => format!("ping {payload:?}"),
+          Event::Pong(payload) => format!("pong {payload:?}"),
+        })
+      }
+    });
+
+  assert_eq!(
+    ws.recv_owned().await.unwrap(),
+    OwnedMessage::Ping(b"hi".to_vec())
+  );
+  assert_eq!(
+    ws.recv_owned().await.unwrap(),
+    OwnedMessage::Pong(Vec::new())
+  );
+  assert!(ws.recv_owned().await.is_err());
+
+  let eof = WSocketError::from(std::io::Error::from(std::io::ErrorKind::UnexpectedEof));
+  assert_eq!(
+    *events.lock().unwrap(),
+    [
+      "open Server".to_string(),
+      "ping [104, 105]".to_string(),
+      "pong []".to_string(),
+      format!("error {eof}"),
+      format!("close {:?}", Initiator::Local),
+    ]
+  );
+}
+
+#[tokio::test]
+async fn test_send_and_recv_frame() -> WSocketResult<()> {
+  use crate::Close;
+
+  let (a, b) = tokio::io::duplex(1024);
+  let mut tx = WebSocket::server(a, config(1024));
+  let mut rx = WebSocket::server(b, config(1024))
+    .with_masking(Masking::Lenient)
+    .with_allowed_rsv(0b100);
+
+  tx.send_frame(Frame::new(true, OpCode::Binary, b"Hello").with_rsv(0b100))
+    .await?;
+  tx.close(Close::new(CloseCode::Normal, None)).await?;
+
+  let (header, payload) = rx.recv_frame().await?;
+  assert_eq!((header.rsv, header.opcode), (0b100, OpCode::Binary));
+  assert_eq!(payload, b"Hello");
+
+  let (header, _) = rx.recv_frame().await?;
+  assert_eq!(header.opcode, OpCode::Close);
+  assert!(rx.is_closed());
+
+  Ok(())
+}
+
+#[tokio::test]
+async fn test_send_close_frame_closes_connection() -> WSocketResult<()> {
+  use crate::{Close, Initiator};
+
+  let (a, b) = tokio::io::duplex(1024);
+  let mut tx = WebSocket::server(a, config(1024));
+  let mut rx = WebSocket::server(b, config(1024)).with_masking(Masking::Lenient);
+
+  assert!(matches!(
+    tx.send_frame(Frame::new(true, OpCode::Pong, &[0; 126]))
+      .await,
+    Err(WSocketError::ControlFrameMustHaveAPayloadLengthOf125BytesOrLess)
+  ));
+  assert!(!tx.is_closed());
+
+  let closed = tx.closed();
+  tx.send_frame(Frame::new(true, OpCode::Close, &[0x03, 0xe8]))
+    .await?;
+
+  assert!(tx.is_closed());
+  assert_eq!(closed.await.initiator, Initiator::Local);
+  assert!(matches!(
+    rx.recv_owned().await,
+    Err(WSocketError::ConnectionClosed(close)) if close == Close::new(CloseCode::Normal, None)
+  ));
+
+  Ok(())
+}
+
+#[tokio::test]
+async fn test_close_is_sent_once() -> WSocketResult<()> {
+  use crate::Close;
+
+  let (a, mut b) = tokio::io::duplex(1024);
+  let mut ws = WebSocket::server(a, config(1024));
+
+  ws.send_frame(Frame::new(true, OpCode::Close, &[])).await?;
+  ws.close(Close::new(CloseCode::Normal, None)).await?;
+  drop(ws);
+
+  let mut output = Vec::new();
+  b.read_to_end(&mut output).await?;
+  assert_eq!(output, [0x88, 0x00]);
+
+  Ok(())
+}
+
+#[tokio::test]
+async fn test_send_fragmented_and_recv_reassembled() -> WSocketResult<()> {
+  let (a, b) = tokio::io::duplex(1024);
+  let mut tx = WebSocket::server(a, config(1024)).with_write_limits(Limits {
+    max_frame_len: 2,
+    ..Limits::new(1024)
+  });
+  let mut rx = WebSocket::server(b, config(1024))
+    .with_masking(Masking::Lenient)
+    .with_read_limits(Limits {
+      max_frame_len: 2,
+      max_message_len: 5,
+      max_fragments: 3,
+    });
+
+  tx.send(Message::Binary(b"Hello")).await?;
+  assert_eq!(
+    rx.recv_owned().await?,
+    OwnedMessage::Binary(b"Hello".to_vec())
+  );
+
+  tx.send(Message::Binary(b"Hello!")).await?;
+  assert!(matches!(
+    rx.recv_owned().await,
+    Err(WSocketError::PayloadTooLarge)
+  ));
+
+  Ok(())
+}
+
+#[tokio::test]
+async fn test_stats() -> WSocketResult<()> {
+  use crate::{Close, ErrorStats, MessageStats};
+
+  let (a, b) = tokio::io::duplex(1024);
+  let (mut read, mut write) = WebSocket::server(a, config(1024))
+    .with_write_limits(Limits {
+      max_frame_len: 2,
+      ..Limits::new(1024)
+    })
+    .with_masking(Masking::Lenient)
+    .split();
+  let mut peer = WebSocket::server(b, config(1024)).with_masking(Masking::Lenient);
+
+  write.feed(Message::Binary(b"Hello")).await?;
+  write.feed(Message::Ping(b"hi")).await?;
+  write
+    .send_frame(Frame::new(true, OpCode::Binary, b"ab"))
+    .await?;
+
+  peer.recv_owned().await?;
+  peer.recv_owned().await?;
+  peer.send(Message::Ping(b"!")).await?;
+  peer.close(Close::new(CloseCode::Normal, None)).await?;
+
+  // the ping is answered by the peer
+  assert_eq!(read.recv_owned().await?, OwnedMessage::Pong(b"hi".to_vec()));
+  assert_eq!(read.recv_owned().await?, OwnedMessage::Ping(b"!".to_vec()));
+  assert!(read.recv_owned().await.is_err());
+
+  let stats = write.stats();
+  assert_eq!(read.stats(), stats);
+  assert_eq!(
+    stats.sent.binary,
+    MessageStats {
+      messages: 1,
+      bytes: 5
+    }
+  );
+  assert_eq!(stats.sent.ping.messages, 1);
+  assert_eq!((stats.sent.frames, stats.sent.fragments), (5, 3));
+  assert_eq!(stats.received.pong.bytes, 2);
+  assert_eq!(stats.received.ping.bytes, 1);
+  assert_eq!(stats.received.close.messages, 1);
+  assert_eq!(stats.received.frames, 3);
+  assert_eq!(stats.errors, ErrorStats::default());
+
+  let input = [0x82, 0x05, 0x48, 0x65, 0x6c, 0x6c, 0x6f];
+  let mut ws = WebSocket::server(Cursor::new(input.to_vec()), config(1024));
+  assert!(ws.recv_owned().await.is_err());
+  assert_eq!(ws.stats().errors.protocol, 1);
+
+  Ok(())
+}
+
+/// Records the fields of spans and events, events together with the id of their parent span.
+#[derive(Clone, Default)]
+struct Recorder {
+  spans: Arc<Mutex<Vec<String>>>,
+  events: Arc<Mutex<Vec<(u64, String)>>>,
+}
+
+struct Fields<'a>(&'a mut String);
+
+impl Visit for Fields<'_> {
+  fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+    match field.name() {
+      "message" => self.0.insert_str(0, &format!("{value:?}")),
+      name => self.0.push_str(&format!(" {name}={value:?}")),
+    }
+  }
+}
+
+impl Subscriber for Recorder {
+  fn enabled(&self, _: &Metadata<'_>) -> bool {
+    true
+  }
+
+  fn new_span(&self, span: &Attributes<'_>) -> Id {
+    let mut fields = String::new();
+    span.record(&mut Fields(&mut fields));
+
+    let mut spans = self.spans.lock().unwrap();
+    spans.push(fields);
+    Id::from_u64(spans.len() as u64)
+  }
+
+  fn record(&self, span: &Id, values: &Record<'_>) {
+    let mut spans = self.spans.lock().unwrap();
+    values.record(&mut Fields(&mut spans[span.into_u64() as usize - 1]));
+  }
+
+  fn record_follows_from(&self, _: &Id, _: &Id) {}
+
+  fn event(&self, event: &tracing::Event<'_>) {
+    let mut fields = String::new();
+    event.record(&mut Fields(&mut fields));
+
+    let parent = event.parent().map_or(0, Id::into_u64);
+    self.events.lock().unwrap().push((parent, fields));
+  }
+
+  fn enter(&self, _: &Id) {}
+
+  fn exit(&self, _: &Id) {}
+}
+
+#[tokio::test]
+async fn test_span() -> WSocketResult<()> {
+  use crate::Close;
+
+  let recorder = Recorder::default();
+  let _guard = tracing::subscriber::set_default(recorder.clone());
+
+  let (a, b) = tokio::io::duplex(1024);
+  let mut ws = WebSocket::server(a, config(1024))
+    .with_peer("127.0.0.1:1234")
+    .with_subprotocol("chat");
+  let mut peer = WebSocket::server(b, config(1024)).with_masking(Masking::Lenient);
+
+  ws.send(Message::Binary(b"Hello")).await?;
+  peer.recv_owned().await?;
+  ws.close(Close::new(CloseCode::Normal, None)).await?;
+
+  let id = ws.span().id().unwrap().into_u64();
+  assert_ne!(peer.span().id().unwrap().into_u64(), id);
+
+  let span = recorder.spans.lock().unwrap()[id as usize - 1].clone();
+  assert!(span.contains(" role=Server"));
+  assert!(span.contains(" peer=127.0.0.1:1234 subprotocol=\"chat\""));
+
+  let events: Vec<_> = recorder
+    .events
+    .lock()
+    .unwrap()
+    .iter()
+    .filter(|(parent, _)| *parent == id)
+    .map(|(_, event)| event.clone())
+    .collect();
+  assert_eq!(events.len(), 3);
+  assert!(events[0].starts_with("message direction=Sent opcode=Binary len=5 frames=1"));
+  assert!(events[1].starts_with("message direction=Sent opcode=Close len=2"));
+  assert!(events[2].starts_with("connection closed"));
+
+  Ok(())
+}
+
+#[tokio::test]
+async fn test_read_header_then_payload() -> WSocketResult<()> {
+  let input = [
+    include_bytes!("../test/frame_65536_in.bin").as_slice(),
+    &[
+      0x89, 0x85, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d, 0x51, 0x58,
+    ],
+    &[0x82, 0x02, 0x48, 0x69],
+  ]
+  .concat();
+  let mut ws =
+    WebSocket::server(Cursor::new(input.to_vec()), config(65536)).with_masking(Masking::Lenient);
+
+  let header = ws.read_header().await?;
+  assert_eq!((header.opcode, header.len), (OpCode::Binary, 65536));
+
+  let mut payload = Vec::new();
+  let mut buf = [0u8; 999];
+
+  loop {
+    match ws.read_payload_into(&mut buf).await? {
+      0 => break,
+      len => payload.extend_from_slice(&buf[..len]),
+    }
+  }
+
+  assert_eq!(payload, include_bytes!("../test/frame_65536_out.bin"));
+
+  let header = ws.read_header().await?;
+  assert_eq!((header.opcode, header.len), (OpCode::Ping, 5));
+  ws.skip_payload().await?;
+
+  assert_eq!(ws.recv_owned().await?, OwnedMessage::Binary(b"Hi".to_vec()));
+
+  Ok(())
+}
+
+/// Yields one byte per read, returning [`Poll::Pending`] in between.
+struct TrickleReader {
+  data: Vec<u8>,
+  pos: usize,
+  ready: bool,
+}
+
+impl TrickleReader {
+  fn new(data: Vec<u8>) -> Self {
+    Self {
+      data,
+      pos: 0,
+      ready: false,
+    }
+  }
+}
+
+impl AsyncRead for TrickleReader {
+  fn poll_read(
+    mut self: Pin<&mut Self>,
+    cx: &mut Context<'_>,
+    buf: &mut ReadBuf<'_>,
+  ) -> Poll<std::io::Result<()>> {
+    if !self.ready {
+      self.ready = true;
+      cx.waker().wake_by_ref();
+      return Poll::Pending;
+    }
+
+    self.ready = false;
+
+    if let Some(&byte) = self.data.get(self.pos) {
+      self.pos += 1;
+      buf.put_slice(&[byte]);
+    }
+
+    Poll::Ready(Ok(()))
+  }
+}
+
+/// Written bytes, like pongs answering received pings, are dropped.
+impl AsyncWrite for TrickleReader {
+  fn poll_write(
+    self: Pin<&mut Self>,
+    _: &mut Context<'_>,
+    buf: &[u8],
+  ) -> Poll<std::io::Result<usize>> {
+    Poll::Ready(Ok(buf.len()))
+  }
+
+  fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+    Poll::Ready(Ok(()))
+  }
+
+  fn poll_shutdown(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+    Poll::Ready(Ok(()))
+  }
+}
+
+/// Polls `future` `polls` times and drops it afterwards, returning whether it completed.
+fn poll_and_cancel<F: Future>(future: F, polls: usize) -> bool {
+  let mut future = pin!(future);
+  let mut cx = Context::from_waker(Waker::noop());
+
+  (0..polls).any(|_| future.as_mut().poll(&mut cx).is_ready())
+}
+
+/// Two masked frames, the second one with a 16 bit extended payload length.
+fn cancellation_input() -> (Vec<u8>, Vec<OwnedMessage>) {
+  let mask = [0x37, 0xfa, 0x21, 0x3d];
+  let payload = (0..200u8).collect::<Vec<_>>();
+
+  let mut input = Vec::new();
+  Frame::new(true, OpCode::Ping, b"Hello").encode(&mut input, Some(mask));
+  Frame::new(true, OpCode::Binary, &payload).encode(&mut input, Some(mask));
+
+  (
+    input,
+    vec![
+      OwnedMessage::Ping(b"Hello".to_vec()),
+      OwnedMessage::Binary(payload),
+    ],
+  )
+}
+
+#[tokio::test]
+async fn test_recv_owned_cancelled_at_every_byte() -> WSocketResult<()> {
+  let (input, expected) = cancellation_input();
+
+  // every byte takes two polls, the first one returning pending
+  for polls in 0..input.len() * 2 {
+    let mut ws = WebSocket::server(TrickleReader::new(input.clone()), config(1024));
+    let mut received = Vec::new();
+
+    if poll_and_cancel(ws.recv_owned(), polls) {
+      // the first message completed, before the future would have been cancelled
+      received.push(expected[0].clone());
+    }
+
+    while received.len() < expected.len() {
+      received.push(ws.recv_owned().await?);
+    }
+
+    assert_eq!(received, expected, "cancelled after {polls} polls");
+  }
+
+  Ok(())
+}
+
+#[tokio::test]
+async fn test_recv_cancelled_at_every_byte() -> WSocketResult<()> {
+  let (input, expected) = cancellation_input();
+  let mut buf = [0u8; 1024];
+
+  for polls in 0..input.len() * 2 {
+    let mut ws = WebSocket::server(TrickleReader::new(input.clone()), config(1024));
+    let mut received = Vec::new();
+
+    if poll_and_cancel(ws.recv(&mut buf), polls) {
+      received.push(expected[0].clone());
+    }
+
+    while received.len() < expected.len() {
+      received.push(ws.recv(&mut buf).await?.into());
+    }
+
+    assert_eq!(received, expected, "cancelled after {polls} polls");
+  }
+
+  Ok(())
+}
+
+#[tokio::test]
+async fn test_read_header_cancelled_at_every_byte() -> WSocketResult<()> {
+  let (input, _) = cancellation_input();
+
+  for polls in 0..input.len() * 2 {
+    let mut ws = WebSocket::server(TrickleReader::new(input.clone()), config(1024));
+    let mut headers = 0;
+
+    if poll_and_cancel(ws.read_header(), polls) {
+      headers += 1;
+    }
+
+    while headers < 2 {
+      ws.read_header().await?;
+      headers += 1;
+    }
+
+    assert_eq!(ws.protocol.payload_remaining(), 200);
+  }
+
+  Ok(())
+}
+
+/// Accepts up to seven bytes per vectored write, returning [`Poll::Pending`] in between.
+#[derive(Default)]
+struct ChokeWriter {
+  written: Vec<u8>,
+  ready: bool,
+}
+
+impl AsyncWrite for ChokeWriter {
+  fn poll_write(
+    self: Pin<&mut Self>,
+    cx: &mut Context<'_>,
+    buf: &[u8],
+  ) -> Poll<std::io::Result<usize>> {
+    self.poll_write_vectored(cx, &[IoSlice::new(buf)])
+  }
+
+  fn poll_write_vectored(
+    mut self: Pin<&mut Self>,
+    cx: &mut Context<'_>,
+    bufs: &[IoSlice<'_>],
+  ) -> Poll<std::io::Result<usize>> {
+    if !self.ready {
+      self.ready = true;
+      cx.waker().wake_by_ref();
+      return Poll::Pending;
+    }
+
+    self.ready = false;
+
+    let buf = bufs
+      .iter()
+      .flat_map(|buf| buf.iter().copied())
+      .take(7)
+      .collect::<Vec<_>>();
+    self.written.extend_from_slice(&buf);
+    Poll::Ready(Ok(buf.len()))
+  }
+
+  fn is_write_vectored(&self) -> bool {
+    true
+  }
+
+  fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+    Poll::Ready(Ok(()))
+  }
+
+  fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+    Poll::Ready(Ok(()))
+  }
+}
+
+#[tokio::test]
+async fn test_feed_cancelled_at_every_write() -> WSocketResult<()> {
+  let payload = (0..64u8).collect::<Vec<_>>();
+
+  let mut frame = Vec::new();
+  Frame::new(true, OpCode::Binary, &payload).encode(&mut frame, None);
+  let mut ping = Vec::new();
+  Frame::new(true, OpCode::Ping, b"Hello").encode(&mut ping, None);
+
+  for polls in 0..frame.len() * 2 {
+    let mut ws =
+      WebSocket::server(ChokeWriter::default(), config(1024)).with_write_high_water_mark(16);
+
+    let completed = poll_and_cancel(ws.feed(Message::Binary(&payload)), polls);
+    ws.send(Message::Ping(b"Hello")).await?;
+
+    // the frame is either sent completely or not at all, but never torn apart
+    let written = &ws.io.written;
+    let sent = completed || written.len() > ping.len();
+    if sent {
+      assert_eq!(
+        written,
+        &[&frame[..], &ping[..]].concat(),
+        "cancelled after {polls} polls"
+      );
+    } else {
+      assert_eq!(written, &ping, "cancelled after {polls} polls");
+    }
+
+    // and counted exactly if it is sent
+    assert_eq!(
+      ws.stats().sent.binary.messages,
+      u64::from(sent),
+      "cancelled after {polls} polls"
+    );
+  }
+
+  Ok(())
+}
+
+/// Receives the close frame the connection sent to `peer`.
+async fn recv_close(peer: tokio::io::DuplexStream) -> Option<crate::Close> {
+  let mut peer = WebSocket::server(peer, config(1024)).with_masking(Masking::Lenient);
+
+  match peer.recv_owned().await {
+    Err(WSocketError::ConnectionClosed(close)) => Some(close),
+    _ => None,
+  }
+}
+
+fn error_close(code: CloseCode, err: WSocketError) -> Option<crate::Close> {
+  Some(crate::Close::new(code, Some(err.to_string())))
+}
+
+#[tokio::test]
+async fn test_idle_timeout() {
+  let (io, peer) = tokio::io::duplex(1024);
+  let mut ws = WebSocket::server(io, config(1024)).with_idle_timeout(Duration::from_millis(20));
+
+  let result = ws.recv_owned().await;
+  assert!(matches!(result, Err(WSocketError::IdleTimeout)));
+  assert_eq!(result.unwrap_err().close_code(), Some(CloseCode::Away));
+  assert!(ws.is_closed());
+  assert_eq!(
+    recv_close(peer).await,
+    error_close(CloseCode::Away, WSocketError::IdleTimeout)
+  );
+}
+
+#[tokio::test]
+async fn test_frame_timeout() -> WSocketResult<()> {
+  let (io, mut peer) = tokio::io::duplex(1024);
+  let mut ws = WebSocket::server(io, config(1024))
+    .with_masking(Masking::Lenient)
+    .with_idle_timeout(Duration::from_secs(60))
+    .with_frame_timeout(Duration::from_millis(20));
+
+  // a complete frame followed by the first bytes of the next one
+  peer
+    .write_all(&[0x82, 0x02, 0x48, 0x69, 0x82, 0x05, 0x48])
+    .await?;
+
+  assert_eq!(ws.recv_owned().await?, OwnedMessage::Binary(b"Hi".to_vec()));
+
+  let result = ws.recv_owned().await;
+  assert!(matches!(result, Err(WSocketError::FrameTimeout)));
+  assert_eq!(
+    result.unwrap_err().close_code(),
+    Some(CloseCode::PolicyViolation)
+  );
+  assert_eq!(
+    recv_close(peer).await,
+    error_close(CloseCode::PolicyViolation, WSocketError::FrameTimeout)
+  );
+
+  Ok(())
+}
+
+#[tokio::test]
+async fn test_recv_error_sends_close() -> WSocketResult<()> {
+  let (io, mut peer) = tokio::io::duplex(1024);
+  let mut ws = WebSocket::server(io, config(4));
+
+  peer.write_all(&[0x82, 0x01, 0x48]).await?;
+  assert!(matches!(
+    ws.recv(&mut [0; 4]).await,
+    Err(WSocketError::FrameMustBeMasked)
+  ));
+  assert_eq!(
+    recv_close(peer).await,
+    error_close(CloseCode::ProtocolError, WSocketError::FrameMustBeMasked)
+  );
+
+  Ok(())
+}
+
+#[tokio::test]
+async fn test_recv_close_echoes_it() -> WSocketResult<()> {
+  let (io, mut peer) = tokio::io::duplex(1024);
+  let mut ws = WebSocket::server(io, config(1024)).with_masking(Masking::Lenient);
+
+  peer.write_all(&[0x88, 0x02, 0x03, 0xe9]).await?;
+
+  assert!(matches!(
+    ws.recv_owned().await,
+    Err(WSocketError::ConnectionClosed(close)) if close == crate::Close::new(CloseCode::Away, None)
+  ));
+
+  let mut echo = [0; 4];
+  peer.read_exact(&mut echo).await?;
+  assert_eq!(echo, [0x88, 0x02, 0x03, 0xe9]);
+
+  Ok(())
+}
+
+#[tokio::test]
+async fn test_write_half_sends_close_after_recv_error() -> WSocketResult<()> {
+  let (io, peer) = tokio::io::duplex(1024);
+  let (mut read, mut write) = WebSocket::server(io, config(4))
+    .with_masking(Masking::Lenient)
+    .split();
+  let mut peer = WebSocket::server(peer, config(1024)).with_masking(Masking::Lenient);
+
+  peer.send(Message::Binary(b"Hello")).await?;
+
+  assert!(matches!(
+    read.recv_owned().await,
+    Err(WSocketError::PayloadTooLarge)
+  ));
+  assert!(matches!(
+    write.flush().await,
+    Err(WSocketError::NotConnected)
+  ));
+  assert!(matches!(
+    peer.recv_owned().await,
+    Err(WSocketError::ConnectionClosed(close))
+      if Some(close.clone()) == error_close(CloseCode::MessageTooBig, WSocketError::PayloadTooLarge)
+  ));
+
+  Ok(())
+}
+
+#[tokio::test]
+async fn test_write_timeout() {
+  let (io, _peer) = tokio::io::duplex(16);
+  let mut ws = WebSocket::server(io, config(1024)).with_write_timeout(Duration::from_millis(20));
+
+  let result = ws.send(Message::Binary(&[0; 1024])).await;
+  assert!(matches!(result, Err(WSocketError::WriteTimeout)));
+  assert!(ws.is_closed());
+}
+
+#[tokio::test]
+async fn test_auto_pong_sent_by_write_half() -> WSocketResult<()> {
+  let (io, peer) = tokio::io::duplex(1024);
+  let (mut read, mut write) = WebSocket::server(io, config(1024))
+    .with_masking(Masking::Lenient)
+    .split();
+  let mut peer = WebSocket::server(peer, config(1024)).with_masking(Masking::Lenient);
+
+  peer.send(Message::Ping(b"Hi")).await?;
+  assert_eq!(read.recv_owned().await?, OwnedMessage::Ping(b"Hi".to_vec()));
+
+  write.flush().await?;
+  assert_eq!(peer.recv_owned().await?, OwnedMessage::Pong(b"Hi".to_vec()));
+
+  Ok(())
+}
+
+#[tokio::test]
+async fn test_auto_pong_sent_while_receiving() -> WSocketResult<()> {
+  let (io, peer) = tokio::io::duplex(1024);
+  let mut ws = WebSocket::server(io, config(1024)).with_masking(Masking::Lenient);
+  let mut peer = WebSocket::server(peer, config(1024)).with_masking(Masking::Lenient);
+
+  peer.send(Message::Ping(b"Hi")).await?;
+  assert_eq!(ws.recv_owned().await?, OwnedMessage::Ping(b"Hi".to_vec()));
+
+  peer.send(Message::Binary(b"Hello")).await?;
+  assert_eq!(
+    ws.recv_owned().await?,
+    OwnedMessage::Binary(b"Hello".to_vec())
+  );
+  assert_eq!(peer.recv_owned().await?, OwnedMessage::Pong(b"Hi".to_vec()));
+
+  Ok(())
+}
+
+#[tokio::test]
+async fn test_keepalive_ping_sent_while_receiving() -> WSocketResult<()> {
+  let (io, peer) = tokio::io::duplex(1024);
+  let mut ws = WebSocket::server(io, config(1024)).with_keepalive(Duration::from_millis(20));
+  let mut peer = WebSocket::server(peer, config(1024)).with_masking(Masking::Lenient);
+
+  let idle = tokio::time::timeout(Duration::from_millis(50), ws.recv_owned()).await;
+  assert!(idle.is_err());
+  assert_eq!(peer.recv_owned().await?, OwnedMessage::Ping(Vec::new()));
+
+  Ok(())
+}
+
+#[tokio::test]
+async fn test_keepalive_ping() -> WSocketResult<()> {
+  let (io, peer) = tokio::io::duplex(1024);
+  let (mut read, mut write) = WebSocket::server(io, config(1024))
+    .with_keepalive(Duration::from_millis(20))
+    .split();
+  let mut peer = WebSocket::server(peer, config(1024)).with_masking(Masking::Lenient);
+
+  let idle = tokio::time::timeout(Duration::from_millis(50), read.recv_owned()).await;
+  assert!(idle.is_err());
+
+  write.flush().await?;
+  assert_eq!(peer.recv_owned().await?, OwnedMessage::Ping(Vec::new()));
+
+  Ok(())
+}
+
+#[tokio::test]
+async fn test_reunite_keeps_buffered_bytes() -> WSocketResult<()> {
+  let (io, mut peer) = tokio::io::duplex(1024);
+  let (mut read, write) = WebSocket::server(io, config(1024))
+    .with_masking(Masking::Lenient)
+    .split();
+
+  peer
+    .write_all(&[0x82, 0x02, 0x48, 0x69, 0x82, 0x03, 0x48])
+    .await?;
+  assert_eq!(
+    read.recv_owned().await?,
+    OwnedMessage::Binary(b"Hi".to_vec())
+  );
+
+  let (other, _other_peer) = tokio::io::duplex(1024);
+  let (_, other_write) = WebSocket::server(other, config(1024)).split();
+  let Err(ReuniteError(read, _)) = read.reunite(other_write) else {
+    panic!("reunited halves of different connections");
+  };
+
+  let mut ws = (*read).reunite(write).unwrap();
+  peer.write_all(&[0x6f, 0x21]).await?;
+  assert_eq!(
+    ws.recv_owned().await?,
+    OwnedMessage::Binary(b"Ho!".to_vec())
+  );
+
+  peer.write_all(&[0x82, 0x05, 0x48]).await?;
+  let idle = tokio::time::timeout(Duration::from_millis(20), ws.recv_owned()).await;
+  assert!(idle.is_err());
+
+  let (_, buffered) = ws.into_inner();
+  assert_eq!(buffered, [0x82, 0x05, 0x48]);
+
+  Ok(())
+}
+
+#[cfg(feature = "net")]
+#[tokio::test]
+async fn test_into_split_tcp_stream() -> WSocketResult<()> {
+  use tokio::net::{TcpListener, TcpStream};
+
+  use crate::Close;
+
+  let listener = TcpListener::bind("127.0.0.1:0").await?;
+  let peer = TcpStream::connect(listener.local_addr()?).await?;
+  let (io, _) = listener.accept().await?;
+
+  let (mut read, mut write) = WebSocket::server(io, config(1024))
+    .with_masking(Masking::Lenient)
+    .into_split();
+  let mut peer = WebSocket::server(peer, config(1024)).with_masking(Masking::Lenient);
+
+  peer.send(Message::Binary(b"Hello")).await?;
+  assert_eq!(
+    read.recv_owned().await?,
+    OwnedMessage::Binary(b"Hello".to_vec())
+  );
+
+  write.send(Message::Binary(b"Hi")).await?;
+  assert_eq!(
+    peer.recv_owned().await?,
+    OwnedMessage::Binary(b"Hi".to_vec())
+  );
+
+  write.close(Close::new(CloseCode::Normal, None)).await?;
+  assert!(read.is_closed());
+
+  Ok(())
+}
+
+#[cfg(feature = "sender")]
+#[tokio::test]
+async fn test_sender_clones_share_writer_task() -> WSocketResult<()> {
+  use crate::OverflowPolicy;
+
+  let (io, peer) = tokio::io::duplex(1024);
+  let (mut read, write) = WebSocket::server(io, config(1024))
+    .with_masking(Masking::Lenient)
+    .split();
+  let mut peer = WebSocket::server(peer, config(1024)).with_masking(Masking::Lenient);
+
+  let sender = write.spawn_writer(8, OverflowPolicy::Block);
+  let other = sender.clone();
+
+  sender.send(OwnedMessage::Binary(b"a".to_vec())).await?;
+  other.send(OwnedMessage::Binary(b"b".to_vec())).await?;
+  assert_eq!(
+    peer.recv_owned().await?,
+    OwnedMessage::Binary(b"a".to_vec())
+  );
+  assert_eq!(
+    peer.recv_owned().await?,
+    OwnedMessage::Binary(b"b".to_vec())
+  );
+
+  // pongs are sent by the writer task right away
+  peer.send(Message::Ping(b"Hi")).await?;
+  assert_eq!(read.recv_owned().await?, OwnedMessage::Ping(b"Hi".to_vec()));
+  assert_eq!(peer.recv_owned().await?, OwnedMessage::Pong(b"Hi".to_vec()));
+
+  Ok(())
+}
+
+#[cfg(feature = "sender")]
+#[tokio::test]
+async fn test_sender_drop_oldest_and_control_priority() -> WSocketResult<()> {
+  use crate::{Close, OverflowPolicy};
+
+  let (io, peer) = tokio::io::duplex(1024);
+  let mut peer = WebSocket::server(peer, config(1024)).with_masking(Masking::Lenient);
+  let (sender, writer) =
+    WebSocket::server(io, config(1024)).into_sender(1, OverflowPolicy::DropOldest);
+
+  sender.send(OwnedMessage::Binary(b"a".to_vec())).await?;
+  sender.send(OwnedMessage::Binary(b"b".to_vec())).await?;
+  sender.send(OwnedMessage::Ping(b"Hi".to_vec())).await?;
+  sender.close(Close::new(CloseCode::Normal, None))?;
+  assert!(matches!(
+    sender.send(OwnedMessage::Binary(b"c".to_vec())).await,
+    Err(WSocketError::NotConnected)
+  ));
+
+  writer.await;
+
+  assert_eq!(peer.recv_owned().await?, OwnedMessage::Ping(b"Hi".to_vec()));
+  assert_eq!(
+    peer.recv_owned().await?,
+    OwnedMessage::Binary(b"b".to_vec())
+  );
+  assert!(matches!(
+    peer.recv_owned().await,
+    Err(WSocketError::ConnectionClosed(close)) if close == Close::new(CloseCode::Normal, None)
+  ));
+
+  Ok(())
+}
+
+#[cfg(feature = "sender")]
+#[tokio::test]
+async fn test_sender_coalesces_pongs_and_echoes_close() -> WSocketResult<()> {
+  use crate::OverflowPolicy;
+
+  let (io, mut peer) = tokio::io::duplex(1024);
+  let (mut read, write) = WebSocket::server(io, config(1024))
+    .with_masking(Masking::Lenient)
+    .split();
+  let (sender, writer) = write.into_sender(2, OverflowPolicy::Block);
+
+  for payload in [b"1", b"2"] {
+    sender.send(OwnedMessage::Ping(payload.to_vec())).await?;
+    sender.send(OwnedMessage::Pong(payload.to_vec())).await?;
+  }
+
+  let writer = tokio::spawn(writer);
+
+  // only the latest pong is sent, but every ping
+  let mut output = [0u8; 9];
+  peer.read_exact(&mut output).await?;
+  assert_eq!(
+    output,
+    [0x8a, 0x01, b'2', 0x89, 0x01, b'1', 0x89, 0x01, b'2']
+  );
+
+  // the peer closes the connection, without answering the ping
+  peer.write_all(&[0x88, 0x02, 0x03, 0xe9]).await?;
+  assert!(matches!(
+    read.recv_owned().await,
+    Err(WSocketError::ConnectionClosed(_))
+  ));
+  writer.await.unwrap();
+
+  let mut output = [0u8; 4];
+  peer.read_exact(&mut output).await?;
+  assert_eq!(output, [0x88, 0x02, 0x03, 0xe9]);
+
+  Ok(())
+}
+
+#[cfg(feature = "sender")]
+#[tokio::test]
+async fn test_sender_block_and_close_on_overflow() -> WSocketResult<()> {
+  use crate::OverflowPolicy;
+
+  let (sender, _writer) =
+    WebSocket::server(Vec::new(), config(1024)).into_sender(1, OverflowPolicy::Block);
+  sender.send(OwnedMessage::Binary(b"a".to_vec())).await?;
+  assert!(!poll_and_cancel(
+    sender.send(OwnedMessage::Binary(b"b".to_vec())),
+    3
+  ));
+
+  let (io, peer) = tokio::io::duplex(1024);
+  let mut peer = WebSocket::server(peer, config(1024)).with_masking(Masking::Lenient);
+  let (sender, writer) = WebSocket::server(io, config(1024)).into_sender(1, OverflowPolicy::Close);
+
+  sender.send(OwnedMessage::Binary(b"a".to_vec())).await?;
+  assert!(matches!(
+    sender.send(OwnedMessage::Binary(b"b".to_vec())).await,
+    Err(WSocketError::QueueFull)
+  ));
+  assert!(sender.is_closed());
+
+  writer.await;
+
+  assert!(matches!(
+    peer.recv_owned().await,
+    Err(WSocketError::ConnectionClosed(close)) if close == WSocketError::QueueFull.to_close()
+  ));
+
+  Ok(())
+}
@@ -0,0 +1,116 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use std::time::Duration;
+
+use tokio::time::{sleep, Instant, Sleep};
+
+use crate::{WSocketError, WSocketResult};
+
+/// Timeouts of a [`WebSocket`](crate::WebSocket), all disabled by default.
+///
+/// They are driven by the timer of tokio, so a tokio runtime with time enabled is required once
+/// any of them is set.
+#[derive(Debug, Copy, Clone, Default)]
+pub(crate) struct Timeouts {
+  pub(crate) write: Option<Duration>,
+  pub(crate) frame: Option<Duration>,
+  pub(crate) idle: Option<Duration>,
+  pub(crate) close: Option<Duration>,
+  pub(crate) keepalive: Option<Duration>,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum ReadTimerKind {
+  /// Waiting for the first byte of the next frame.
+  Idle,
+  /// Waiting for the rest of a partially received frame.
+  Frame,
+}
+
+/// Timer for the read currently waiting for bytes, kept across polls and cancelled receives.
+#[derive(Default)]
+pub(crate) struct ReadTimer {
+  timer: Option<(ReadTimerKind, Pin<Box<Sleep>>)>,
+  keepalive: Option<Pin<Box<Sleep>>>,
+}
+
+impl ReadTimer {
+  /// Polls the timeout for waiting on more bytes, either of the frame partially received or of
+  /// the next frame.
+  pub(crate) fn poll_expired(
+    &mut self,
+    cx: &mut Context<'_>,
+    timeouts: &Timeouts,
+    partial: bool,
+  ) -> Poll<WSocketError> {
+    let (kind, duration) = if partial {
+      (ReadTimerKind::Frame, timeouts.frame)
+    } else {
+      (ReadTimerKind::Idle, timeouts.idle)
+    };
+
+    let Some(duration) = duration else {
+      self.timer = None;
+      return Poll::Pending;
+    };
+
+    let (_, timer) = match &mut self.timer {
+      Some(timer) if timer.0 == kind => timer,
+      timer => timer.insert((kind, Box::pin(sleep(duration)))),
+    };
+
+    match timer.as_mut().poll(cx) {
+      Poll::Ready(()) => Poll::Ready(match kind {
+        ReadTimerKind::Idle => WSocketError::IdleTimeout,
+        ReadTimerKind::Frame => WSocketError::FrameTimeout,
+      }),
+      Poll::Pending => Poll::Pending,
+    }
+  }
+
+  /// Polls the keepalive interval, ready once nothing has been received for a whole interval.
+  /// The interval restarts right away, so it becomes ready again after another interval.
+  pub(crate) fn poll_keepalive(&mut self, cx: &mut Context<'_>, timeouts: &Timeouts) -> Poll<()> {
+    let Some(interval) = timeouts.keepalive else {
+      self.keepalive = None;
+      return Poll::Pending;
+    };
+
+    let timer = self
+      .keepalive
+      .get_or_insert_with(|| Box::pin(sleep(interval)));
+
+    ready!(timer.as_mut().poll(cx));
+    timer.as_mut().reset(Instant::now() + interval);
+
+    Poll::Ready(())
+  }
+
+  /// Bytes have been received, so the connection is no longer idle.
+  pub(crate) fn on_received(&mut self) {
+    if matches!(self.timer, Some((ReadTimerKind::Idle, _))) {
+      self.timer = None;
+    }
+
+    self.keepalive = None;
+  }
+
+  /// A complete frame has been received, the next one gets a timer of its own.
+  pub(crate) fn on_frame(&mut self) {
+    self.timer = None;
+  }
+}
+
+/// Fails with [`WSocketError::WriteTimeout`] if `future` doesn't complete within `timeout`.
+pub(crate) async fn write_timeout<T>(
+  timeout: Option<Duration>,
+  future: impl Future<Output = WSocketResult<T>>,
+) -> WSocketResult<T> {
+  match timeout {
+    Some(timeout) => tokio::time::timeout(timeout, future)
+      .await
+      .unwrap_or(Err(WSocketError::WriteTimeout)),
+    None => future.await,
+  }
+}
@@ -1,79 +1,348 @@
-use tokio::io::{AsyncWrite, AsyncWriteExt};
-use tokio::select;
-use tracing::{error, info};
+use std::future::poll_fn;
+use std::io;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
 
-use crate::frame::{Frame, OpCode};
-use crate::{Close, CloseCode, Message, WSocketError, WSocketResult, WebSocket};
+use tokio::io::AsyncWrite;
+use tracing::{error, info, Span};
+
+use super::event::Initiator;
+use super::stats::{Direction, StatsCounters};
+use super::{record_frame, record_message};
+use crate::frame::FrameWrite;
+use crate::ws::timeout::write_timeout;
+use crate::{
+  Close, Frame, Message, OpCode, Protocol, Role, WSocketError, WSocketResult, WebSocket,
+};
 
 impl<W: Unpin + AsyncWrite> WebSocket<W> {
+  /// Sends the message and flushes it, together with all previously [fed](Self::feed) messages.
+  ///
+  /// # Cancel safety
+  ///
+  /// If cancelled, the message has either not been sent at all or is sent completely, possibly
+  /// only with the next flush. The peer never receives parts of a frame followed by another one.
   pub async fn send(&mut self, message: Message<'_>) -> WSocketResult<()> {
-    if self.is_closed() {
-      return Err(WSocketError::NotConnected)?;
+    self.feed(message).await?;
+    self.flush().await
+  }
+
+  /// Queues the message in the write buffer without flushing it. The buffer is only written, once
+  /// it exceeds the write high-water mark or [`flush`](Self::flush) is called, so multiple messages
+  /// are coalesced into a single write.
+  ///
+  /// Messages exceeding the [`max_frame_len`](crate::Limits::max_frame_len) of the write limits
+  /// are split into fragments.
+  ///
+  /// # Cancel safety
+  ///
+  /// Same as [`send`](Self::send), a cancelled message is either dropped or sent completely.
+  pub async fn feed(&mut self, message: Message<'_>) -> WSocketResult<()> {
+    self
+      .feed_outbound(Outbound::Message(Frame::from(message)))
+      .await
+  }
+
+  /// Sends the frame as is and flushes it, together with all previously [fed](Self::feed)
+  /// messages. It is masked if acting as [`Role::Client`].
+  ///
+  /// Fragmenting messages, as well as interleaving control frames correctly, is up to the caller.
+  /// Control frames have to be final and must not exceed 125 bytes. Close frames are sent using
+  /// [`close`](Self::close), marking the connection as closed.
+  pub async fn send_frame(&mut self, frame: Frame<'_>) -> WSocketResult<()> {
+    if frame.opcode == OpCode::Close {
+      frame.check_control()?;
+      return self.close(Close::parse(frame.data)?).await;
     }
 
-    let frame = match message {
-      Message::Binary(data) => Frame::new(true, OpCode::Binary, data),
-      Message::Text(text) => Frame::new(true, OpCode::Text, text.as_bytes()),
-      Message::Ping(data) => Frame::new(true, OpCode::Ping, data),
-      Message::Pong(data) => Frame::new(true, OpCode::Pong, data),
-    };
+    self.feed_frame(frame).await?;
+    self.flush().await
+  }
+
+  /// Same as [`feed`](Self::feed), but for a single frame, see [`send_frame`](Self::send_frame).
+  pub async fn feed_frame(&mut self, frame: Frame<'_>) -> WSocketResult<()> {
+    frame.check_control()?;
+
+    if frame.opcode == OpCode::Close {
+      if self.is_closed() {
+        return Err(WSocketError::NotConnected);
+      }
+
+      return self.queue_close(Close::parse(frame.data)?);
+    }
 
-    let mut close = self.close.subscribe();
+    self.feed_outbound(Outbound::Frame(frame)).await
+  }
+
+  async fn feed_outbound(&mut self, outbound: Outbound<'_>) -> WSocketResult<()> {
+    if self.is_closed() {
+      return self.fail_closed().await;
+    }
 
     // aboard send if connection got closed
-    let result = select! {
-      result = self.send_frame(frame) => {
-        if let Err(ref err) = result {
-          let close = Close::new(
-            err.close_code().unwrap_or(CloseCode::InternalError),
-            Some(format!("{}", err))
-          );
-
-          if !err.is_io_error() {
-            if let Err(err) = self.close(close).await {
-              error!("Failed to send close frame: {}", err);
-            }
-          } else {
-            info!("Marking write channel as closed");
-            self.set_closed(close);
-          }
-        }
-        result
-      },
-      // TODO: is unwrap ok here?
-      result = close.recv() => return Err(WSocketError::ConnectionClosed(result.unwrap())),
-    };
+    let closed = self.closed.clone();
+    let timeout = self.timeouts.write;
+    let result = closed
+      .or_closed(write_timeout(timeout, self.buffer(outbound)))
+      .await;
+
+    match result {
+      Err(WSocketError::ConnectionClosed(_)) if closed.is_closed() => {}
+      Err(ref err) => self.on_send_error(err).await,
+      Ok(_) => {}
+    }
+
+    result
+  }
+
+  /// Writes all buffered messages and flushes the underlying io.
+  ///
+  /// # Cancel safety
+  ///
+  /// This method is cancel safe, bytes not yet written stay buffered for the next flush.
+  pub async fn flush(&mut self) -> WSocketResult<()> {
+    if self.is_closed() {
+      return self.fail_closed().await;
+    }
+
+    let closed = self.closed.clone();
+    let timeout = self.timeouts.write;
+    let result = closed
+      .or_closed(write_timeout(timeout, async {
+        self.encode_control()?;
+        self.flush_frames().await
+      }))
+      .await;
 
-    // mark stream as closed and send close frame, if error wasn't an io error
+    match result {
+      Err(WSocketError::ConnectionClosed(_)) if closed.is_closed() => {}
+      Err(ref err) => self.on_send_error(err).await,
+      Ok(_) => {}
+    }
 
     result
   }
 
+  /// Sends the close frame and marks the connection as closed. Sending it may take up to the
+  /// close timeout, falling back to the write timeout. If a close frame has been sent already,
+  /// only the remaining output is flushed.
+  ///
+  /// A close frame queued by the reading side, because receiving failed or the peer closed the
+  /// connection, is sent instead.
   pub async fn close(&mut self, close: Close) -> WSocketResult<()> {
-    let buf = close.encode()?;
-    let frame = Frame::new(true, OpCode::Close, &buf);
-    self.set_closed(close.clone());
-    self.send_frame(frame).await
+    self.encode_control()?;
+    self.queue_close(close)?;
+
+    let timeout = self.timeouts.close.or(self.timeouts.write);
+    write_timeout(timeout, self.flush_frames()).await
+  }
+
+  /// Marks the connection as closed and appends the close frame to the write buffer, unless one
+  /// has been sent already.
+  pub(crate) fn queue_close(&mut self, close: Close) -> WSocketResult<()> {
+    if self.protocol.is_close_sent() {
+      return Ok(());
+    }
+
+    self.protocol.close(&close)?;
+    self.record_message(Direction::Sent, OpCode::Close, close.payload_len(), 1);
+    self.set_closed(close, Initiator::Local);
+    Ok(())
+  }
+
+  /// Sends the close frame queued by the reading side, if any. Sending it may take up to the close
+  /// timeout, falling back to the write timeout.
+  pub(crate) async fn send_queued_close(&mut self) -> WSocketResult<()> {
+    if !self.control.is_pending() {
+      return Ok(());
+    }
+
+    self.encode_control()?;
+
+    let timeout = self.timeouts.close.or(self.timeouts.write);
+    write_timeout(timeout, self.flush_frames()).await
   }
 
-  async fn send_frame(&mut self, frame: Frame<'_>) -> WSocketResult<()> {
-    if frame.data.len() > self.max_payload_len {
-      return Err(WSocketError::PayloadTooLarge);
+  /// Fails a write to the closed connection, sending the close frame queued by the reading side
+  /// first.
+  async fn fail_closed(&mut self) -> WSocketResult<()> {
+    if let Err(err) = self.send_queued_close().await {
+      error!(parent: &self.span, "Failed to send close frame: {}", err);
     }
 
-    #[cfg(not(feature = "client"))]
-    frame.write_without_mask(&mut self.io).await?;
+    Err(WSocketError::NotConnected)
+  }
+
+  /// Mark stream as closed and send close frame, if error wasn't an io error.
+  async fn on_send_error(&mut self, err: &WSocketError) {
+    let close = err.to_close();
+    self.report_error(err);
 
-    #[cfg(feature = "client")]
-    if self.masking {
-      let mask = rand::random();
-      frame.write_with_mask(&mut self.io, mask).await?;
+    if !err.is_io_error() {
+      if let Err(err) = self.close(close.clone()).await {
+        error!(parent: &self.span, "Failed to send close frame: {}", err);
+        self.set_closed(close, Initiator::Local);
+      }
     } else {
-      frame.write_without_mask(&mut self.io).await?;
+      info!(parent: &self.span, "Marking write channel as closed");
+      self.set_closed(close, Initiator::Local);
     }
+  }
+
+  async fn buffer(&mut self, outbound: Outbound<'_>) -> WSocketResult<()> {
+    self.encode_control()?;
 
-    self.io.flush().await?;
+    let (frame, is_message) = match outbound {
+      Outbound::Message(frame) if frame.data.len() > self.protocol.write_limits().max_frame_len => {
+        self.encode_message(frame)?;
+        return self.write_above_high_water_mark().await;
+      }
+      Outbound::Message(frame) => {
+        self.protocol.check_message_len(frame.data.len())?;
+        (frame, true)
+      }
+      Outbound::Frame(frame) => (frame, false),
+    };
+
+    // large payloads are not copied into the write buffer, but written right behind it
+    if self.protocol.role() == Role::Server && frame.data.len() >= self.write_high_water_mark {
+      frame.check_control()?;
+      self.protocol.check_frame_len(frame.data.len())?;
+
+      let io = &mut self.io;
+      let mut write = DirectWrite {
+        protocol: &mut self.protocol,
+        frame: FrameWrite::new(&frame),
+        sent: frame,
+        is_message,
+        stats: &self.stats,
+        span: &self.span,
+      };
+
+      return poll_fn(|cx| write.frame.poll_write(cx, io, write.protocol)).await;
+    }
+
+    self.protocol.encode(frame)?;
+    record_sent(&self.stats, &self.span, &frame, is_message);
+    self.write_above_high_water_mark().await
+  }
+
+  /// Appends the message to the write buffer, split into fragments if required.
+  pub(crate) fn encode_message(&mut self, frame: Frame<'_>) -> WSocketResult<()> {
+    let frames = self.protocol.frame_count(&frame);
+    self.protocol.encode_message(frame)?;
+    self.record_message(Direction::Sent, frame.opcode, frame.data.len(), frames);
+    Ok(())
+  }
+
+  /// Appends the control frames queued by the reading side to the write buffer. Once the
+  /// connection is closed, only the close frame is appended.
+  pub(crate) fn encode_control(&mut self) -> WSocketResult<()> {
+    let (pong, ping, close) = self.control.take();
+
+    if let Some(close) = close {
+      return self.queue_close(close);
+    }
+
+    if self.is_closed() {
+      return Ok(());
+    }
+
+    if let Some(payload) = pong {
+      self.encode_message(Frame::from(Message::Pong(&payload)))?;
+    }
+
+    if ping {
+      self.encode_message(Frame::from(Message::Ping(&[])))?;
+    }
+
+    Ok(())
+  }
+
+  /// Writes the control frames queued by the reading side. Used while receiving, if this
+  /// connection owns the writing side of the io as well.
+  pub(crate) fn poll_write_control(&mut self, cx: &mut Context<'_>) -> Poll<WSocketResult<()>> {
+    self.encode_control()?;
+    self.poll_flush_frames(cx)
+  }
+
+  /// Writes the write buffer, once it exceeds the write high-water mark.
+  async fn write_above_high_water_mark(&mut self) -> WSocketResult<()> {
+    if self.protocol.output().len() >= self.write_high_water_mark {
+      poll_fn(|cx| self.poll_write_buffered(cx)).await?;
+    }
 
     Ok(())
   }
+
+  async fn flush_frames(&mut self) -> WSocketResult<()> {
+    poll_fn(|cx| self.poll_flush_frames(cx)).await
+  }
+
+  /// Writes the write buffer and flushes the underlying io.
+  pub(crate) fn poll_flush_frames(&mut self, cx: &mut Context<'_>) -> Poll<WSocketResult<()>> {
+    ready!(self.poll_write_buffered(cx))?;
+    ready!(Pin::new(&mut self.io).poll_flush(cx))?;
+
+    Poll::Ready(Ok(()))
+  }
+
+  /// Writes the write buffer, keeping track of partial writes.
+  pub(crate) fn poll_write_buffered(&mut self, cx: &mut Context<'_>) -> Poll<WSocketResult<()>> {
+    while !self.protocol.output().is_empty() {
+      let written = ready!(Pin::new(&mut self.io).poll_write(cx, self.protocol.output()))?;
+
+      if written == 0 {
+        return Poll::Ready(Err(io::Error::from(io::ErrorKind::WriteZero).into()));
+      }
+
+      self.protocol.consume_output(written);
+    }
+
+    Poll::Ready(Ok(()))
+  }
+}
+
+/// A frame to be sent, holding either a whole message or a frame sent as is.
+enum Outbound<'a> {
+  /// Split into fragments, if it exceeds the max frame length.
+  Message(Frame<'a>),
+  Frame(Frame<'a>),
+}
+
+/// A frame written right behind the write buffer. If the write is cancelled after parts of the
+/// frame have been written, the rest is moved into the write buffer, to be sent with the next
+/// flush. Otherwise the stream would continue in the middle of a frame.
+struct DirectWrite<'a, 'b> {
+  protocol: &'a mut Protocol,
+  frame: FrameWrite<'b>,
+  sent: Frame<'b>,
+  is_message: bool,
+  stats: &'a StatsCounters,
+  span: &'a Span,
+}
+
+/// Once started, the frame is sent completely, even if cancelled, so it's counted right away.
+impl Drop for DirectWrite<'_, '_> {
+  fn drop(&mut self) {
+    if !self.frame.is_started() {
+      return;
+    }
+
+    if !self.frame.is_done() {
+      self.frame.buffer_rest(self.protocol);
+    }
+
+    record_sent(self.stats, self.span, &self.sent, self.is_message);
+  }
+}
+
+fn record_sent(stats: &StatsCounters, span: &Span, frame: &Frame<'_>, is_message: bool) {
+  let (opcode, len) = (frame.opcode, frame.data.len());
+
+  if is_message {
+    record_message(stats, span, Direction::Sent, opcode, len, 1);
+  } else {
+    record_frame(stats, span, Direction::Sent, opcode, len, frame.fin);
+  }
 }